@@ -0,0 +1,83 @@
+use std::slice;
+
+use {BucketLimit, Bounds, Centered, Coordinates, Metric, NTree, Region, SplitPolicy, Summary};
+use NTreeVariant::{Branch, Bucket};
+
+/// A part of an n-tree as seen from a target point: either a node far
+/// enough away to be approximated by its summary, or a single point.
+#[derive(Debug, PartialEq)]
+pub enum Approximation<'t, R: 't, P: 't, A: 't> {
+    /// A node standing in for all of its points.
+    Node {
+        /// The region of the node.
+        region: &'t R,
+        /// The summary of every point within the node.
+        summary: &'t A
+    },
+    /// A point from a node too close to approximate.
+    Point(&'t P)
+}
+
+/// An iterator over an n-tree approximated from a target point, as in
+/// the Barnes–Hut algorithm.
+//
+// This walks the tree like `Iter`, except that a node passing the
+// opening criterion is handed back whole rather than descended into.
+pub struct Approximations<'t, 'p, 'm, R: 't, P: 't + 'p + PartialEq, M: 'm, S: 't = BucketLimit, A: 't = ()> {
+    target: &'p P,
+    theta: f64,
+    metric: &'m M,
+    points: slice::Iter<'t, P>,
+    stack: Vec<slice::Iter<'t, NTree<R, P, S, A>>>
+}
+
+impl<'t, 'p, 'm, R, P, M, S, A> Approximations<'t, 'p, 'm, R, P, M, S, A>
+where R: Region<P> + Bounds, P: PartialEq + Coordinates, M: Metric<P, R>, S: SplitPolicy<R>, A: Summary<P> + Centered<P> {
+    pub(crate) fn new(tree: &'t NTree<R, P, S, A>, target: &'p P, theta: f64, metric: &'m M)
+                      -> Approximations<'t, 'p, 'm, R, P, M, S, A> {
+        Approximations { target, theta, metric, points: [].iter(), stack: vec![slice::from_ref(tree).iter()] }
+    }
+
+    // Is the node far enough from the target to stand in for its
+    // points? Its widest side must be less than theta times the
+    // distance from the target to its center, and a node containing
+    // the target is never far enough.
+    fn is_far(&self, node: &NTree<R, P, S, A>) -> bool {
+        if node.region.contains(self.target) { return false }
+
+        let size = (0..self.target.dimensions())
+            .map(|axis| node.region.upper(axis) - node.region.lower(axis))
+            .fold(0.0, f64::max);
+        size < self.theta * self.metric.distance(self.target, &node.summary.center())
+    }
+}
+
+impl<'t, 'p, 'm, R, P, M, S, A> Iterator for Approximations<'t, 'p, 'm, R, P, M, S, A>
+where R: Region<P> + Bounds, P: PartialEq + Coordinates, M: Metric<P, R>, S: SplitPolicy<R>, A: Summary<P> + Centered<P> {
+    type Item = Approximation<'t, R, P, A>;
+
+    fn next(&mut self) -> Option<Approximation<'t, R, P, A>> {
+        loop {
+            if let Some(p) = self.points.next() {
+                return Some(Approximation::Point(p))
+            }
+
+            // find the next node holding any points, dropping exhausted levels.
+            let node = loop {
+                match self.stack.last_mut()?.next() {
+                    Some(node) => if !node.is_empty() { break node },
+                    None => { self.stack.pop(); }
+                }
+            };
+
+            if self.is_far(node) {
+                return Some(Approximation::Node { region: &node.region, summary: &node.summary })
+            }
+
+            match node.kind {
+                Bucket { ref points, .. } => self.points = points.iter(),
+                Branch { ref subregions, .. } => self.stack.push(subregions.iter())
+            }
+        }
+    }
+}
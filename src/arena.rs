@@ -0,0 +1,435 @@
+//! An n-tree whose nodes and points live in contiguous arenas.
+
+use std::ops::Range;
+use std::slice;
+
+use {BucketLimit, Query, Region, SplitPolicy, DEFAULT_MAX_DEPTH};
+use self::Kind::{Branch, Bucket};
+
+// The capacity of the smallest block of point slots handed to a bucket.
+const MIN_BLOCK: usize = 4;
+
+/// An n-tree which keeps all of its nodes in one `Vec` and all of its
+/// points in another, addressing them by index rather than by pointer.
+///
+/// The children of a branch sit next to each other in the node arena,
+/// and the points of a bucket sit next to each other in a block of the
+/// point arena, with room to grow. Blocks come in power-of-two sizes,
+/// and a block given up by a bucket which outgrew it, split or merged
+/// is reused by the next bucket needing one that size, so a tree which
+/// has reached its working size stops allocating.
+///
+/// Nodes left behind by a merge are likewise reused by the next split.
+///
+/// This is not faster than `NTree` to build or query. An `NTree` already
+/// keeps the children of a branch in one `Vec` and the points of a
+/// bucket in another, so both layouts touch much the same memory, and
+/// the arena's slots are larger than bare points. What it saves is the
+/// allocator traffic of splitting and merging.
+pub struct ArenaNTree<R, P, S = BucketLimit> {
+    nodes: Vec<Node<R>>,
+    // The first `len` slots of a bucket's block hold its points, and
+    // every other slot is empty.
+    slots: Vec<Option<P>>,
+    policy: S,
+    len: usize,
+    // Freed blocks, by the log2 of their capacity.
+    free_blocks: Vec<Vec<usize>>,
+    // The first nodes of freed runs of siblings, by the length of the run.
+    free_nodes: Vec<Vec<usize>>
+}
+
+struct Node<R> {
+    region: R,
+    kind: Kind
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    /// A leaf of the tree, whose points are in a block of slots.
+    Bucket {
+        start: usize,
+        len: usize,
+        capacity: usize,
+        depth: usize
+    },
+    /// An interior node of the tree, whose n subtrees are adjacent.
+    Branch {
+        first: usize,
+        count: usize
+    }
+}
+
+impl<R: Region<P>, P: PartialEq> ArenaNTree<R, P> {
+    /// Create a new, empty n-tree over the region, whose buckets are
+    /// limited to the passed-in size.
+    ///
+    /// The tree is limited to a depth of `DEFAULT_MAX_DEPTH`.
+    pub fn new(region: R, size: u8) -> ArenaNTree<R, P> {
+        ArenaNTree::with_policy(region, BucketLimit {
+            bucket_limit: size as usize,
+            max_depth: DEFAULT_MAX_DEPTH as usize
+        })
+    }
+}
+
+impl<R: Region<P>, P: PartialEq, S: SplitPolicy<R>> ArenaNTree<R, P, S> {
+    /// Create a new, empty n-tree over the region, whose buckets split
+    /// and merge as the policy decides.
+    ///
+    /// As with `NTree`, the root starts out split once and may later
+    /// merge back into a bucket.
+    pub fn with_policy(region: R, policy: S) -> ArenaNTree<R, P, S> {
+        let mut tree = ArenaNTree {
+            nodes: vec![Node { region, kind: Bucket { start: 0, len: 0, capacity: 0, depth: 0 } }],
+            slots: vec![],
+            policy,
+            len: 0,
+            free_blocks: vec![],
+            free_nodes: vec![]
+        };
+        tree.branch(0, 0);
+        tree
+    }
+
+    /// Insert a point into the n-tree, returns true if the point
+    /// is within the n-tree and was inserted and false if not.
+    pub fn insert(&mut self, point: P) -> bool {
+        if !self.nodes[0].region.contains(&point) { return false }
+        let mut idx = 0;
+        while let Branch { first, count } = self.nodes[idx].kind {
+            idx = self.child_containing(first, count, &point);
+        }
+
+        if let Bucket { len, depth, .. } = self.nodes[idx].kind {
+            if self.policy.should_split(&self.nodes[idx].region, depth, len + 1) {
+                // Bucket is full
+                let mut points = self.take_points(idx);
+                points.push(point);
+                self.branch(idx, depth);
+                self.insert_all(idx, points);
+            } else {
+                self.push(idx, point);
+            }
+        }
+
+        self.len += 1;
+        true
+    }
+
+    /// Remove a point from the n-tree, returns true if the point
+    /// was found and removed and false if not.
+    ///
+    /// As with `NTree::remove`, branches the policy allows to merge are
+    /// collapsed back into buckets.
+    pub fn remove(&mut self, point: &P) -> bool {
+        if !self.nodes[0].region.contains(point) { return false }
+        let removed = self.remove_from(0, point);
+        if removed { self.len -= 1 }
+        removed
+    }
+
+    /// Get all the points within the queried region, as
+    /// `NTree::range_query` does.
+    ///
+    /// The query can be any shape implementing `Query`.
+    pub fn range_query<'t, 'q, Q>(&'t self, query: &'q Q) -> RangeQuery<'t, 'q, R, P, Q>
+    where Q: Query<R, P> + ?Sized {
+        RangeQuery {
+            query,
+            nodes: &self.nodes,
+            slots: &self.slots,
+            points: [].iter(),
+            // the root is node zero.
+            stack: vec![Range { start: 0, end: 1 }]
+        }
+    }
+
+    /// Iterate over all the points in the n-tree.
+    ///
+    /// This walks the point arena from end to end, rather than the
+    /// tree, so the points come out in no particular order.
+    pub fn iter<'t>(&'t self) -> Iter<'t, P> {
+        Iter { slots: self.slots.iter(), len: self.len }
+    }
+
+    /// The number of points in the n-tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Does the n-tree hold no points?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Is the point contained in the n-tree?
+    pub fn contains(&self, point: &P) -> bool {
+        self.nodes[0].region.contains(point)
+    }
+
+    /// Iterate over all the points in the bucket containing a
+    /// specified point.
+    pub fn nearby<'t>(&'t self, point: &P) -> Option<Iter<'t, P>> {
+        if !self.nodes[0].region.contains(point) { return None }
+
+        let mut idx = 0;
+        while let Branch { first, count } = self.nodes[idx].kind {
+            idx = self.child_containing(first, count, point);
+        }
+
+        match self.nodes[idx].kind {
+            Bucket { start, len, .. } => Some(Iter { slots: self.slots[start..start + len].iter(), len }),
+            Branch { .. } => unreachable!()
+        }
+    }
+
+    fn child_containing(&self, first: usize, count: usize, point: &P) -> usize {
+        (first..first + count)
+            .find(|&idx| self.nodes[idx].region.contains(point))
+            .unwrap() //does always exist, due to invariant of R.split()
+    }
+
+    // Turn the empty bucket at idx into a branch of empty buckets.
+    fn branch(&mut self, idx: usize, depth: usize) {
+        let regions = self.nodes[idx].region.split();
+        let count = regions.len();
+        let children = regions
+            .into_iter()
+            .map(|region| Node { region, kind: Bucket { start: 0, len: 0, capacity: 0, depth: depth + 1 } });
+
+        let first = match self.free_nodes.get_mut(count).and_then(|free| free.pop()) {
+            Some(first) => {
+                for (node, child) in self.nodes[first..first + count].iter_mut().zip(children) {
+                    *node = child;
+                }
+                first
+            },
+            None => {
+                let first = self.nodes.len();
+                self.nodes.extend(children);
+                first
+            }
+        };
+        self.nodes[idx].kind = Branch { first, count };
+    }
+
+    // Insert many points, all of which are contained in the node's region.
+    fn insert_all(&mut self, idx: usize, mut points: Vec<P>) {
+        if let Bucket { len, depth, .. } = self.nodes[idx].kind {
+            if !self.policy.should_split(&self.nodes[idx].region, depth, len + points.len()) {
+                self.reserve(idx, len + points.len());
+                for point in points {
+                    self.push(idx, point);
+                }
+                return
+            }
+
+            // Too many points for this bucket, so split it and partition
+            // everything among the new sub-regions below.
+            points.extend(self.take_points(idx));
+            self.branch(idx, depth);
+        }
+
+        if let Branch { first, count } = self.nodes[idx].kind {
+            let mut partitions: Vec<Vec<P>> = (0..count).map(|_| vec![]).collect();
+            for point in points {
+                let child = self.child_containing(first, count, &point);
+                partitions[child - first].push(point);
+            }
+
+            for (child, partition) in (first..first + count).zip(partitions) {
+                if !partition.is_empty() {
+                    self.insert_all(child, partition);
+                }
+            }
+        }
+    }
+
+    fn remove_from(&mut self, idx: usize, point: &P) -> bool {
+        match self.nodes[idx].kind {
+            Bucket { start, ref mut len, .. } => {
+                let found = self.slots[start..start + *len]
+                    .iter()
+                    .position(|x| x.as_ref() == Some(point));
+                match found {
+                    Some(pos) => {
+                        // Keep the points at the front of the block.
+                        *len -= 1;
+                        self.slots.swap(start + pos, start + *len);
+                        self.slots[start + *len] = None;
+                        true
+                    },
+                    None => false
+                }
+            },
+            Branch { first, count } => {
+                let child = self.child_containing(first, count, point);
+                let removed = self.remove_from(child, point);
+                if removed {
+                    self.merge(idx, first, count);
+                }
+                removed
+            }
+        }
+    }
+
+    // Collapse the branch at idx into a bucket if its children are all
+    // buckets and the policy allows.
+    fn merge(&mut self, idx: usize, first: usize, count: usize) {
+        let mut total = 0;
+        let mut depth = 0;
+        for child in first..first + count {
+            match self.nodes[child].kind {
+                Bucket { len, depth: child_depth, .. } => {
+                    total += len;
+                    depth = child_depth - 1;
+                },
+                Branch { .. } => return
+            }
+        }
+        if !self.policy.should_merge(&self.nodes[idx].region, depth, total) { return }
+
+        let mut points = Vec::with_capacity(total);
+        for child in first..first + count {
+            points.extend(self.take_points(child));
+        }
+        if self.free_nodes.len() <= count {
+            self.free_nodes.resize_with(count + 1, Vec::new);
+        }
+        self.free_nodes[count].push(first);
+
+        self.nodes[idx].kind = Bucket { start: 0, len: 0, capacity: 0, depth };
+        self.reserve(idx, total);
+        for point in points {
+            self.push(idx, point);
+        }
+    }
+
+    // Empty the bucket at idx, giving up its block.
+    fn take_points(&mut self, idx: usize) -> Vec<P> {
+        match self.nodes[idx].kind {
+            Bucket { start, len, capacity, depth } => {
+                let points = self.slots[start..start + len]
+                    .iter_mut()
+                    .map(|slot| slot.take().unwrap()) //the first len slots are full
+                    .collect();
+                self.free_block(start, capacity);
+                self.nodes[idx].kind = Bucket { start: 0, len: 0, capacity: 0, depth };
+                points
+            },
+            Branch { .. } => unreachable!()
+        }
+    }
+
+    // Add a point to the bucket at idx, moving it to a bigger block if
+    // its own is full.
+    fn push(&mut self, idx: usize, point: P) {
+        if let Bucket { len, .. } = self.nodes[idx].kind {
+            self.reserve(idx, len + 1);
+        }
+        if let Bucket { start, ref mut len, .. } = self.nodes[idx].kind {
+            self.slots[start + *len] = Some(point);
+            *len += 1;
+        }
+    }
+
+    // Make sure the bucket at idx has room for this many points.
+    fn reserve(&mut self, idx: usize, needed: usize) {
+        let (start, len, capacity, depth) = match self.nodes[idx].kind {
+            Bucket { start, len, capacity, depth } => (start, len, capacity, depth),
+            Branch { .. } => unreachable!()
+        };
+        if needed <= capacity { return }
+
+        let new_capacity = needed.next_power_of_two().max(MIN_BLOCK).max(capacity * 2);
+        let new_start = self.alloc_block(new_capacity);
+        for i in 0..len {
+            self.slots[new_start + i] = self.slots[start + i].take();
+        }
+        self.free_block(start, capacity);
+        self.nodes[idx].kind = Bucket { start: new_start, len, capacity: new_capacity, depth };
+    }
+
+    fn alloc_block(&mut self, capacity: usize) -> usize {
+        let class = capacity.trailing_zeros() as usize;
+        if let Some(start) = self.free_blocks.get_mut(class).and_then(|free| free.pop()) {
+            return start
+        }
+
+        let start = self.slots.len();
+        self.slots.resize_with(start + capacity, || None);
+        start
+    }
+
+    fn free_block(&mut self, start: usize, capacity: usize) {
+        if capacity == 0 { return }
+        let class = capacity.trailing_zeros() as usize;
+        if self.free_blocks.len() <= class {
+            self.free_blocks.resize_with(class + 1, Vec::new);
+        }
+        self.free_blocks[class].push(start);
+    }
+}
+
+/// An iterator over the points of an `ArenaNTree` within a region.
+//
+// This is `::RangeQuery`, over ranges of node indices rather than
+// slices of child nodes.
+pub struct RangeQuery<'t, 'q, R: 't, P: 't, Q: 'q + ?Sized = R> {
+    query: &'q Q,
+    nodes: &'t [Node<R>],
+    slots: &'t [Option<P>],
+    points: slice::Iter<'t, Option<P>>,
+    stack: Vec<Range<usize>>
+}
+
+impl<'t, 'q, R, P, Q: Query<R, P> + ?Sized> Iterator for RangeQuery<'t, 'q, R, P, Q> {
+    type Item = &'t P;
+
+    fn next(&mut self) -> Option<&'t P> {
+        loop {
+            for p in (&mut self.points).flatten() {
+                if self.query.matches(p) {
+                    return Some(p)
+                }
+            }
+
+            // find the next overlapping node, dropping exhausted levels.
+            let node = loop {
+                match self.stack.last_mut()?.next() {
+                    Some(idx) => if self.query.may_overlap(&self.nodes[idx].region) { break &self.nodes[idx] },
+                    None => { self.stack.pop(); }
+                }
+            };
+
+            match node.kind {
+                Bucket { start, len, .. } => self.points = self.slots[start..start + len].iter(),
+                Branch { first, count } => self.stack.push(first..first + count)
+            }
+        }
+    }
+}
+
+/// An iterator over points of an `ArenaNTree`.
+pub struct Iter<'t, P: 't> {
+    slots: slice::Iter<'t, Option<P>>,
+    // The number of points left.
+    len: usize
+}
+
+impl<'t, P> Iterator for Iter<'t, P> {
+    type Item = &'t P;
+
+    fn next(&mut self) -> Option<&'t P> {
+        let p = (&mut self.slots).flatten().next()?;
+        self.len -= 1;
+        Some(p)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'t, P> ExactSizeIterator for Iter<'t, P> {}
@@ -0,0 +1,312 @@
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use {BucketLimit, Query, Region, SplitPolicy, DEFAULT_MAX_DEPTH};
+use self::Kind::{Branch, Bucket};
+
+/// An n-tree which many threads can insert into, remove from and query
+/// at once.
+///
+/// Every node has its own lock. Operations hold read locks on the
+/// branches along their path, and only write-lock the bucket they
+/// change, so threads working in different buckets don't wait for each
+/// other. A bucket is split, or a branch merged, while holding the lock
+/// of that node alone: its parent stays read-locked throughout, so no
+/// other operation can be part-way into the node being replaced.
+///
+/// Queries can't borrow points out of the locked nodes, so they return
+/// copies. Each bucket is read consistently, but a query running
+/// alongside writers may see some of their changes and not others.
+pub struct ConcurrentNTree<R, P, S = BucketLimit> {
+    root: Node<R, P, S>,
+    // The number of points, kept apart from the nodes so counting them
+    // takes no locks. It only ever counts, and orders nothing else, so
+    // relaxed updates are enough.
+    len: AtomicUsize
+}
+
+struct Node<R, P, S> {
+    region: R,
+    kind: RwLock<Kind<R, P, S>>
+}
+
+enum Kind<R, P, S> {
+    /// A leaf of the tree, which contains points.
+    Bucket {
+        points: Vec<P>,
+        policy: S,
+        depth: usize
+    },
+    /// An interior node of the tree, which contains n subtrees.
+    Branch {
+        subregions: Vec<Node<R, P, S>>
+    }
+}
+
+impl<R: Region<P>, P: PartialEq> ConcurrentNTree<R, P> {
+    /// Create a new, empty n-tree over the region, whose buckets are
+    /// limited to the passed-in size.
+    ///
+    /// The tree is limited to a depth of `DEFAULT_MAX_DEPTH`.
+    pub fn new(region: R, size: u8) -> ConcurrentNTree<R, P> {
+        ConcurrentNTree::with_policy(region, BucketLimit {
+            bucket_limit: size as usize,
+            max_depth: DEFAULT_MAX_DEPTH as usize
+        })
+    }
+}
+
+impl<R: Region<P>, P: PartialEq, S: SplitPolicy<R>> ConcurrentNTree<R, P, S> {
+    /// Create a new, empty n-tree over the region, whose buckets split
+    /// and merge as the policy decides.
+    pub fn with_policy(region: R, policy: S) -> ConcurrentNTree<R, P, S> {
+        let kind = Node::split(&region, &policy, 0, vec![]);
+        ConcurrentNTree { root: Node { region, kind: RwLock::new(kind) }, len: AtomicUsize::new(0) }
+    }
+
+    /// Insert a point into the n-tree, returns true if the point
+    /// is within the n-tree and was inserted and false if not.
+    pub fn insert(&self, point: P) -> bool {
+        if !self.root.region.contains(&point) { return false }
+        self.root.insert(point);
+        self.len.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Remove a point from the n-tree, returns true if the point
+    /// was found and removed and false if not.
+    ///
+    /// As with `NTree::remove`, branches the policy allows to merge are
+    /// collapsed back into buckets.
+    pub fn remove(&self, point: &P) -> bool {
+        if !self.root.region.contains(point) { return false }
+        let removed = self.root.remove(point);
+        if removed {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            self.root.merge();
+        }
+        removed
+    }
+
+    /// Get copies of all the points within the queried region.
+    ///
+    /// As with `NTree::range_query`, the query can be any shape
+    /// implementing `Query`.
+    pub fn range_query<Q: Query<R, P> + ?Sized>(&self, query: &Q) -> Vec<P> where P: Clone {
+        let mut found = vec![];
+        self.root.range_query(query, &mut found);
+        found
+    }
+
+    /// Get copies of all the points in the bucket containing a
+    /// specified point.
+    pub fn nearby(&self, point: &P) -> Option<Vec<P>> where P: Clone {
+        if !self.root.region.contains(point) { return None }
+        Some(self.root.nearby(point))
+    }
+
+    /// The number of points in the n-tree.
+    ///
+    /// The count is kept as points are inserted and removed, so this
+    /// takes constant time and no locks. Inserts and removes still in
+    /// progress on other threads may not be counted yet.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Does the n-tree hold no points?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Is the point contained in the n-tree?
+    pub fn contains(&self, point: &P) -> bool {
+        self.root.region.contains(point)
+    }
+}
+
+impl<R: Region<P>, P: PartialEq, S: SplitPolicy<R>> Node<R, P, S> {
+    // A panic while a lock is held never leaves a node half-changed,
+    // since splits and merges build the replacement node first, so
+    // poisoned locks are used as they are.
+    fn read(&self) -> RwLockReadGuard<'_, Kind<R, P, S>> {
+        self.kind.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Kind<R, P, S>> {
+        self.kind.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // A bucket of the points, or a branch if the policy says to split.
+    fn bucket(region: R, policy: &S, depth: usize, points: Vec<P>) -> Node<R, P, S> {
+        let kind = if policy.should_split(&region, depth, points.len()) {
+            Node::split(&region, policy, depth, points)
+        } else {
+            Bucket { points, policy: policy.clone(), depth }
+        };
+        Node { region, kind: RwLock::new(kind) }
+    }
+
+    // A branch over the region with the points partitioned among its
+    // sub-regions, which split further as the policy decides.
+    fn split(region: &R, policy: &S, depth: usize, points: Vec<P>) -> Kind<R, P, S> {
+        let subregions = region.split();
+        let mut partitions: Vec<Vec<P>> = subregions.iter().map(|_| vec![]).collect();
+        for point in points {
+            let idx = subregions
+                .iter()
+                .position(|sub_region| sub_region.contains(&point))
+                .unwrap(); //does always exist, due to invariant of R.split()
+            partitions[idx].push(point);
+        }
+
+        Branch {
+            subregions: subregions
+                .into_iter()
+                .zip(partitions)
+                .map(|(sub_region, points)| Node::bucket(sub_region, policy, depth + 1, points))
+                .collect()
+        }
+    }
+
+    fn insert(&self, point: P) {
+        if let Branch { ref subregions } = *self.read() {
+            return Node::insert_into(subregions, point)
+        }
+
+        // This was a bucket, but another thread may have split it
+        // between releasing the read lock and taking the write lock.
+        let mut guard = self.write();
+        let branch = match *guard {
+            Branch { ref subregions } => return Node::insert_into(subregions, point),
+            Bucket { ref mut points, ref policy, depth } => {
+                if !policy.should_split(&self.region, depth, points.len() + 1) {
+                    points.push(point);
+                    return
+                }
+
+                let mut points = mem::take(points);
+                points.push(point);
+                Node::split(&self.region, policy, depth, points)
+            }
+        };
+        *guard = branch;
+    }
+
+    fn insert_into(subregions: &[Node<R, P, S>], point: P) {
+        subregions
+            .iter()
+            .find(|sub_node| sub_node.region.contains(&point))
+            .unwrap() //does always exist, due to invariant of R.split()
+            .insert(point)
+    }
+
+    fn remove(&self, point: &P) -> bool {
+        if let Branch { ref subregions } = *self.read() {
+            return Node::remove_from(subregions, point)
+        }
+
+        let mut guard = self.write();
+        match *guard {
+            Branch { ref subregions } => Node::remove_from(subregions, point),
+            Bucket { ref mut points, .. } => {
+                match points.iter().position(|x| x == point) {
+                    Some(idx) => {
+                        points.swap_remove(idx);
+                        true
+                    },
+                    None => false
+                }
+            }
+        }
+    }
+
+    // Remove the point from the sub-region containing it, then merge
+    // that sub-region if it is left small enough. The caller holds a
+    // lock on the parent the whole time.
+    fn remove_from(subregions: &[Node<R, P, S>], point: &P) -> bool {
+        let sub_node = subregions
+            .iter()
+            .find(|sub_node| sub_node.region.contains(point))
+            .unwrap(); //does always exist, due to invariant of R.split()
+
+        let removed = sub_node.remove(point);
+        if removed {
+            sub_node.merge();
+        }
+        removed
+    }
+
+    // Collapse a branch of buckets back into a bucket, if the policy
+    // allows. This is checked under a read lock first, so the common
+    // case of nothing to merge doesn't block other threads.
+    fn merge(&self) {
+        if self.mergeable(&self.read()).is_none() { return }
+
+        let mut guard = self.write();
+        let (policy, depth) = match self.mergeable(&guard) {
+            Some(merge) => merge,
+            None => return
+        };
+
+        let mut points = vec![];
+        if let Branch { subregions } = mem::replace(&mut *guard, Bucket { points: vec![], policy: policy.clone(), depth }) {
+            for sub_node in subregions {
+                if let Bucket { points: sub_points, .. } = sub_node.kind.into_inner().unwrap_or_else(PoisonError::into_inner) {
+                    points.extend(sub_points);
+                }
+            }
+        }
+        *guard = Bucket { points, policy, depth };
+    }
+
+    // The policy and depth of the bucket this would merge into, if it
+    // is a branch of buckets the policy allows to merge.
+    fn mergeable(&self, kind: &Kind<R, P, S>) -> Option<(S, usize)> {
+        let subregions = match *kind {
+            Branch { ref subregions } => subregions,
+            Bucket { .. } => return None
+        };
+
+        let mut total = 0;
+        let mut merge = None;
+        for sub_node in subregions {
+            match *sub_node.read() {
+                Bucket { ref points, ref policy, depth } => {
+                    total += points.len();
+                    merge = Some((policy.clone(), depth - 1));
+                },
+                Branch { .. } => return None
+            }
+        }
+
+        merge.filter(|&(ref policy, depth)| policy.should_merge(&self.region, depth, total))
+    }
+
+    fn range_query<Q: Query<R, P> + ?Sized>(&self, query: &Q, found: &mut Vec<P>) where P: Clone {
+        if !query.may_overlap(&self.region) { return }
+
+        match *self.read() {
+            Bucket { ref points, .. } => found.extend(points.iter().filter(|p| query.matches(p)).cloned()),
+            Branch { ref subregions } => {
+                for sub_node in subregions {
+                    sub_node.range_query(query, found);
+                }
+            }
+        }
+    }
+
+    fn nearby(&self, point: &P) -> Vec<P> where P: Clone {
+        match *self.read() {
+            Bucket { ref points, .. } => points.clone(),
+            Branch { ref subregions } => {
+                subregions
+                    .iter()
+                    .find(|sub_node| sub_node.region.contains(point))
+                    .unwrap() //does always exist, due to invariant of R.split()
+                    .nearby(point)
+            }
+        }
+    }
+}
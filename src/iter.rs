@@ -0,0 +1,122 @@
+use std::{slice, vec};
+
+use {BucketLimit, NTree};
+use NTreeVariant::{Branch, Bucket};
+
+/// An iterator over all the points in an n-tree.
+//
+// Like `RangeQuery`, this keeps the points of the current bucket and a
+// stack of iterators over the remaining children of each ancestor, but
+// never needs to prune a region.
+pub struct Iter<'t, R: 't, P: 't + PartialEq, S: 't = BucketLimit, A: 't = ()> {
+    points: slice::Iter<'t, P>,
+    stack: Vec<slice::Iter<'t, NTree<R, P, S, A>>>
+}
+
+impl<'t, R, P: PartialEq, S, A> Iter<'t, R, P, S, A> {
+    pub(crate) fn new(tree: &'t NTree<R, P, S, A>) -> Iter<'t, R, P, S, A> {
+        Iter { points: [].iter(), stack: vec![slice::from_ref(tree).iter()] }
+    }
+}
+
+impl<'t, R, P: PartialEq, S, A> Iterator for Iter<'t, R, P, S, A> {
+    type Item = &'t P;
+
+    fn next(&mut self) -> Option<&'t P> {
+        loop {
+            if let Some(p) = self.points.next() {
+                return Some(p)
+            }
+
+            // find the next node, dropping exhausted levels.
+            let node = loop {
+                match self.stack.last_mut()?.next() {
+                    Some(node) => break node,
+                    None => { self.stack.pop(); }
+                }
+            };
+
+            match node.kind {
+                Bucket { ref points, .. } => self.points = points.iter(),
+                Branch { ref subregions, .. } => self.stack.push(subregions.iter())
+            }
+        }
+    }
+}
+
+/// A mutable iterator over all the points in an n-tree.
+///
+/// Points must not be changed in a way that moves them out of the
+/// region of the bucket they are stored in, or that changes which
+/// sub-region would be picked for them. Doing so leaves them where
+/// later lookups will not find them.
+pub struct IterMut<'t, R: 't, P: 't + PartialEq, S: 't = BucketLimit, A: 't = ()> {
+    points: slice::IterMut<'t, P>,
+    stack: Vec<slice::IterMut<'t, NTree<R, P, S, A>>>
+}
+
+impl<'t, R, P: PartialEq, S, A> IterMut<'t, R, P, S, A> {
+    pub(crate) fn new(tree: &'t mut NTree<R, P, S, A>) -> IterMut<'t, R, P, S, A> {
+        IterMut { points: [].iter_mut(), stack: vec![slice::from_mut(tree).iter_mut()] }
+    }
+}
+
+impl<'t, R, P: PartialEq, S, A> Iterator for IterMut<'t, R, P, S, A> {
+    type Item = &'t mut P;
+
+    fn next(&mut self) -> Option<&'t mut P> {
+        loop {
+            if let Some(p) = self.points.next() {
+                return Some(p)
+            }
+
+            let node = loop {
+                match self.stack.last_mut()?.next() {
+                    Some(node) => break node,
+                    None => { self.stack.pop(); }
+                }
+            };
+
+            match node.kind {
+                Bucket { ref mut points, .. } => self.points = points.iter_mut(),
+                Branch { ref mut subregions, .. } => self.stack.push(subregions.iter_mut())
+            }
+        }
+    }
+}
+
+/// An owning iterator over all the points in an n-tree.
+pub struct IntoIter<R, P: PartialEq, S = BucketLimit, A = ()> {
+    points: vec::IntoIter<P>,
+    stack: Vec<vec::IntoIter<NTree<R, P, S, A>>>
+}
+
+impl<R, P: PartialEq, S, A> IntoIter<R, P, S, A> {
+    pub(crate) fn new(tree: NTree<R, P, S, A>) -> IntoIter<R, P, S, A> {
+        IntoIter { points: vec![].into_iter(), stack: vec![vec![tree].into_iter()] }
+    }
+}
+
+impl<R, P: PartialEq, S, A> Iterator for IntoIter<R, P, S, A> {
+    type Item = P;
+
+    fn next(&mut self) -> Option<P> {
+        loop {
+            if let Some(p) = self.points.next() {
+                return Some(p)
+            }
+
+            let node = loop {
+                match self.stack.last_mut()?.next() {
+                    Some(node) => break node,
+                    None => { self.stack.pop(); }
+                }
+            };
+
+            match node.kind {
+                Bucket { points, .. } => self.points = points.into_iter(),
+                Branch { subregions, .. } => self.stack.push(subregions.into_iter())
+            }
+        }
+    }
+}
@@ -3,32 +3,106 @@
 #![cfg_attr(feature = "bench", feature(test))]
 
 //! A generic, n-dimensional quadtree for fast neighbor lookups on multiple axes.
+//!
+//! With the `serde` feature, n-trees, the default split policy and the
+//! ready-made regions can be serialized and deserialized. With the
+//! `mmap` feature, n-trees can be written to a compact binary file and
+//! queried from it in place, as a `MappedNTree`. With the `rayon`
+//! feature, n-trees can be iterated over and queried in parallel.
 
-extern crate ref_slice;
+#[cfg(feature = "mmap")]
+extern crate crc32fast;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde;
 
 use std::{mem, slice};
 use self::NTreeVariant::{Branch, Bucket};
+use query::HeldQuery;
+
+pub use approximate::{Approximation, Approximations};
+pub use arena::ArenaNTree;
+pub use concurrent::ConcurrentNTree;
+pub use iter::{IntoIter, Iter, IterMut};
+pub use map::NTreeMap;
+#[cfg(feature = "mmap")]
+pub use mapped::{FixedSize, MappedNTree, MappedNearestNeighbors, MappedPoints, MappedRangeQuery};
+pub use objects::{Bounded, ObjectQuery, ObjectTree};
+pub use metric::{Bounds, Chebyshev, Coordinates, Euclidean, Manhattan, Metric};
+pub use nearest::{NearestNeighbors, RadiusQuery};
+#[cfg(feature = "rayon")]
+pub use parallel::{ParIter, ParRangeQuery};
+pub use persistent::PersistentNTree;
+pub use policy::{BucketLimit, SplitPolicy};
+pub use query::Query;
+pub use stats::Stats;
+pub use summary::{Centered, Summary};
+
+mod approximate;
+pub mod arena;
+mod concurrent;
+mod iter;
+pub mod map;
+#[cfg(feature = "mmap")]
+mod mapped;
+mod metric;
+mod nearest;
+mod objects;
+#[cfg(feature = "rayon")]
+mod parallel;
+pub mod persistent;
+mod policy;
+mod query;
+pub mod regions;
+#[cfg(feature = "serde")]
+mod serialize;
+mod stats;
+mod summary;
 
 #[cfg(test)]
 mod test;
 
+/// The maximum depth of an n-tree created with `NTree::new`.
+pub const DEFAULT_MAX_DEPTH: u8 = 32;
+
 /// The required interface for Regions in this n-tree.
 ///
 /// Regions must be able to split themselves, tell if they overlap
 /// other regions, and tell if a point is contained within the region.
 pub trait Region<P>: Clone {
     /// Does this region contain this point?
-    fn contains(&self, &P) -> bool;
+    fn contains(&self, point: &P) -> bool;
 
     /// Split this region, returning a Vec of sub-regions.
     ///
     /// Invariants:
-    ///   - The sub-regions must NOT overlap.
-    ///   - All points in self must be contained within one and only one sub-region.
+    ///   - The sub-regions must NOT overlap, except along shared edges.
+    ///   - All points in self must be contained within at least one sub-region.
+    ///
+    /// A point on an edge shared by several sub-regions belongs to the
+    /// first of them, in the order returned, which contains it. Every
+    /// n-tree routes points this way, so `split` must return the same
+    /// sub-regions in the same order each time it is called.
     fn split(&self) -> Vec<Self>;
 
     /// Does this region overlap with this other region?
     fn overlaps(&self, other: &Self) -> bool;
+
+    /// Does this region entirely contain this other region?
+    ///
+    /// `ObjectTree` uses this to find the deepest region each object
+    /// fits in, and range counts and aggregates use it to take a region
+    /// within the query whole, from its cached count or summary. The
+    /// default never reports containment, which is always safe but keeps
+    /// every object at the root of an `ObjectTree` and every range count
+    /// testing each point.
+    fn contains_region(&self, other: &Self) -> bool {
+        let _ = other;
+        false
+    }
 }
 
 /// A quadtree-like structure, but for arbitrary arity.
@@ -37,20 +111,43 @@ pub trait Region<P>: Clone {
 /// allowing this structure to be used to index data by any number
 /// of attributes and quickly query for data that falls within a
 /// specific range.
-pub struct NTree<R, P:PartialEq> {
+///
+/// When buckets split and branches merge is decided by the split
+/// policy, which by default is a `BucketLimit`.
+///
+/// Every node can also keep a `Summary` of the points beneath it, such
+/// as their total mass, for aggregate queries which needn't visit every
+/// point. Trees built with `summarized` choose the summary; others
+/// summarize nothing.
+///
+/// With the `serde` feature, the whole node structure is serialized, so
+/// a deserialized n-tree has exactly the same shape as the original. The
+/// nodes are written as a flat list rather than nested, so even the
+/// deepest trees load in formats with recursion limits. The split policy
+/// is written once and shared by every bucket on load. On load, the
+/// regions of branches are split again and every point is checked to
+/// lie in the bucket it would be routed to.
+pub struct NTree<R, P:PartialEq, S = BucketLimit, A = ()> {
     region: R,
-    kind: NTreeVariant<R, P>
+    kind: NTreeVariant<R, P, S, A>,
+    // The summary of every point in this node. This is recomputed on load.
+    summary: A
 }
 
-enum NTreeVariant<R, P:PartialEq> {
+enum NTreeVariant<R, P:PartialEq, S, A> {
     /// A leaf of the tree, which contains points.
     Bucket {
         points: Vec<P>,
-        bucket_limit: u8
+        policy: S,
+        // How far this bucket is below the root, for the policy.
+        depth: usize
     },
     /// An interior node of the tree, which contains n subtrees.
     Branch {
-        subregions: Vec<NTree<R, P>>
+        subregions: Vec<NTree<R, P, S, A>>,
+        // The number of points in all the subtrees, so they can be
+        // counted without visiting them. This is recounted on load.
+        len: usize
     }
 }
 
@@ -60,7 +157,69 @@ impl<P:PartialEq, R: Region<P>> NTree<R, P> {
     ///
     /// The number of regions returned by region.split() dictates
     /// the arity of the tree.
+    ///
+    /// The tree is limited to a depth of `DEFAULT_MAX_DEPTH`.
     pub fn new(region: R, size: u8) -> NTree<R, P> {
+        NTree::with_max_depth(region, size, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Create a new n-tree which contains points within the region,
+    /// whose buckets are limited to the passed-in size, and which
+    /// will not split regions beyond the passed-in depth.
+    ///
+    /// Buckets at the maximum depth become overflow buckets, which
+    /// keep accepting points past the bucket limit. This keeps many
+    /// identical or nearly identical points from splitting the tree
+    /// forever.
+    pub fn with_max_depth(region: R, size: u8, max_depth: u8) -> NTree<R, P> {
+        NTree::with_policy(region, BucketLimit {
+            bucket_limit: size as usize,
+            max_depth: max_depth as usize
+        })
+    }
+
+    /// Create a new n-tree holding all of the passed-in points which
+    /// lie within the region, with buckets limited to the passed-in size.
+    ///
+    /// Rather than inserting the points one at a time, this partitions
+    /// them top-down among the sub-regions, splitting each region at
+    /// most once. This avoids repeatedly re-inserting the contents of
+    /// buckets as they fill up.
+    ///
+    /// An n-tree can't be built with `FromIterator`, since it needs a
+    /// region, but `Extend` uses the same approach for existing trees.
+    pub fn from_points<I>(region: R, size: u8, points: I) -> NTree<R, P>
+    where I: IntoIterator<Item=P> {
+        let mut tree = NTree::new(region, size);
+        tree.extend(points);
+        tree
+    }
+}
+
+impl<P:PartialEq, R: Region<P>, S: SplitPolicy<R>> NTree<R, P, S> {
+    /// Create a new n-tree which contains points within the region,
+    /// and whose buckets split and merge as the policy decides.
+    ///
+    /// The root starts out split once, whatever the policy, and merges
+    /// back into a bucket like any other branch when the policy allows.
+    pub fn with_policy(region: R, policy: S) -> NTree<R, P, S> {
+        NTree::summarized(region, policy)
+    }
+}
+
+impl<P:PartialEq, R: Region<P>, S: SplitPolicy<R>, A: Summary<P>> NTree<R, P, S, A> {
+    /// Create a new n-tree which contains points within the region,
+    /// whose buckets split and merge as the policy decides, and whose
+    /// nodes each keep a summary of the points beneath them.
+    ///
+    /// The root starts out split once, whatever the policy, and merges
+    /// back into a bucket like any other branch when the policy allows.
+    pub fn summarized(region: R, policy: S) -> NTree<R, P, S, A> {
+        NTree::branch(region, &policy, 0)
+    }
+
+    // A branch at the given depth, split into empty buckets.
+    fn branch(region: R, policy: &S, depth: usize) -> NTree<R, P, S, A> {
         NTree {
             kind: Branch {
                 subregions: region
@@ -68,11 +227,14 @@ impl<P:PartialEq, R: Region<P>> NTree<R, P> {
                     .into_iter()
                     .map(|r| NTree {
                         region: r,
-                        kind: Bucket { points: vec![], bucket_limit: size }
+                        kind: Bucket { points: vec![], policy: policy.clone(), depth: depth + 1 },
+                        summary: A::empty()
                     })
                     .collect(),
+                len: 0
             },
-            region: region
+            region,
+            summary: A::empty()
         }
     }
 
@@ -80,66 +242,104 @@ impl<P:PartialEq, R: Region<P>> NTree<R, P> {
     /// is within the n-tree and was inserted and false if not.
     pub fn insert(&mut self, point: P) -> bool {
         if !self.region.contains(&point) { return false }
+        let single = A::of(&point);
         let mut current_node = self;
-        loop{
-            match {current_node} {
-                &mut NTree {region: _, kind: Branch { ref mut subregions }} => {
-                    current_node = subregions
-                        .iter_mut()
-                        .find(|sub_node| sub_node.region.contains(&point))
-                        .unwrap(); //does always exist, due to invariant of R.split()
-                },
-                mut node  => {
-                    match node.kind {
-                        Bucket {ref mut points, ref bucket_limit} => {
-                            if points.len() as u8 != *bucket_limit {
-                                points.push(point);
-                                return true;
-                            }
-                        },
-                        _ => unreachable!()
-                    }
-                            
-                    // Bucket is full
-                    split_and_insert(&mut node, point);
+        while let Branch { ref mut subregions, ref mut len } = current_node.kind {
+            *len += 1;
+            current_node.summary = current_node.summary.combine(&single);
+            current_node = subregions
+                .iter_mut()
+                .find(|sub_node| sub_node.region.contains(&point))
+                .unwrap(); //does always exist, due to invariant of R.split()
+        }
+
+        match current_node.kind {
+            Bucket { ref mut points, ref policy, depth } => {
+                if !policy.should_split(&current_node.region, depth, points.len() + 1) {
+                    points.push(point);
+                    current_node.summary = current_node.summary.combine(&single);
                     return true;
                 }
-            }
+            },
+            _ => unreachable!()
         }
+
+        // Bucket is full
+        split_and_insert(current_node, point);
+        true
     }
 
-    /// remove
+    /// Remove a point from the n-tree, returns true if the point
+    /// was found and removed and false if not.
+    ///
+    /// Any branch left holding no more points than fit in a single
+    /// bucket is collapsed back into a bucket.
     pub fn remove(&mut self, point: &P) -> bool {
-        if !self.region.contains(&point) { return false }
-        let mut current_node = self;
-        loop{
-            match {current_node} {
-                &mut NTree {region: _, kind: Branch { ref mut subregions }} => {
-                    current_node = subregions
-                        .iter_mut()
-                        .find(|sub_node| sub_node.region.contains(&point))
-                        .unwrap(); //does always exist, due to invariant of R.split()
-                },
-                node  => {
-                    match node.kind {
-                        Bucket {ref mut points, .. } => {
-                            match points.iter().position(|x|{x == point}) {
-                                None => return false,
-                                Some(idx) => {
-                                    points.swap_remove(idx);
-                                    if points.len() as u8 == 0 {
-                                        // Bucket is empty
-                                        // merge(self, point);
-                                    }
-                                    return true
-                                },
-                            }
-                        },
-                        _ => unreachable!()
-                    }
-                }
+        self.remove_by(&|region: &R| region.contains(point), &|x: &P| x == point).is_some()
+    }
+
+    // Remove the first point accepted by matches from the bucket found
+    // by following route, which picks the region to descend into at
+    // each level, then merge any branch left small enough on the way up.
+    pub(crate) fn remove_by<F, G>(&mut self, route: &F, matches: &G) -> Option<P>
+    where F: Fn(&R) -> bool, G: Fn(&P) -> bool {
+        if !route(&self.region) { return None }
+
+        let removed = match self.kind {
+            Bucket { ref mut points, .. } => {
+                points
+                    .iter()
+                    .position(matches)
+                    .map(|idx| points.swap_remove(idx))
+            },
+            Branch { ref mut subregions, ref mut len } => {
+                let removed = subregions
+                    .iter_mut()
+                    .find(|sub_node| route(&sub_node.region))
+                    .unwrap() //does always exist, due to invariant of R.split()
+                    .remove_by(route, matches);
+                if removed.is_some() { *len -= 1 }
+                removed
+            }
+        };
+
+        if removed.is_some() {
+            self.resummarize();
+            if let Branch { .. } = self.kind {
+                merge(self);
             }
         }
+
+        removed
+    }
+
+    /// Move a point to a new position, returns true if the old point
+    /// was found and moved and false if not.
+    ///
+    /// If the new position would be routed to the same bucket the
+    /// point is updated in place. Otherwise it is re-inserted from the nearest
+    /// ancestor whose region contains the new position, rather than
+    /// from the root. If the new position is outside the n-tree,
+    /// nothing is changed and false is returned.
+    pub fn relocate(&mut self, old: &P, new: P) -> bool {
+        if !self.region.contains(old) || !self.region.contains(&new) { return false }
+
+        match relocate_within(self, old, new, true) {
+            Relocation::Done => true,
+            Relocation::NotFound => false,
+            Relocation::Escaped(_) => unreachable!()
+        }
+    }
+
+    /// Move many points to new positions, as `relocate` does, returns
+    /// the number of points which were moved.
+    pub fn relocate_all<I>(&mut self, moves: I) -> usize
+    where I: IntoIterator<Item=(P, P)> {
+        let mut moved = 0;
+        for (old, new) in moves {
+            if self.relocate(&old, new) { moved += 1 }
+        }
+        moved
     }
 
     /// Get all the points which within the queried region.
@@ -147,14 +347,127 @@ impl<P:PartialEq, R: Region<P>> NTree<R, P> {
     /// Finds all points which are located in regions overlapping
     /// the passed in region, then filters out all points which
     /// are not strictly within the region.
-    pub fn range_query<'t, 'q>(&'t self, query: &'q R) -> RangeQuery<'t, 'q, R, P> {
-        RangeQuery {
-            query: query,
-            points: (&[]).iter(),
-            stack: vec![ref_slice::ref_slice(self).iter()],
+    ///
+    /// The query can be any shape implementing `Query`, not only the
+    /// region type the n-tree is split on.
+    pub fn range_query<'t, 'q, Q>(&'t self, query: &'q Q) -> RangeQuery<'t, 'q, R, P, S, A, Q>
+    where Q: Query<R, P> + ?Sized {
+        RangeQuery { search: Search::new(self, query) }
+    }
+
+    /// The number of points in the n-tree.
+    ///
+    /// Each branch keeps count of the points below it, so this takes
+    /// constant time.
+    pub fn len(&self) -> usize {
+        match self.kind {
+            Bucket { ref points, .. } => points.len(),
+            Branch { len, .. } => len
+        }
+    }
+
+    /// Does the n-tree hold no points?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Count the points within the queried region, as
+    /// `range_query(query).count()` would.
+    ///
+    /// Regions entirely within the query are counted from the cached
+    /// sizes of their subtrees, so only points in buckets straddling the
+    /// query's boundary are tested. This needs a query which reports
+    /// such regions through `Query::covers`, as every region does when
+    /// it implements `Region::contains_region`.
+    pub fn range_count<Q: Query<R, P> + ?Sized>(&self, query: &Q) -> usize {
+        if !query.may_overlap(&self.region) { return 0 }
+        if query.covers(&self.region) { return self.len() }
+
+        match self.kind {
+            Bucket { ref points, .. } => points.iter().filter(|p| query.matches(p)).count(),
+            Branch { ref subregions, .. } => subregions.iter().map(|sub_node| sub_node.range_count(query)).sum()
         }
     }
 
+    /// The summary of all the points in the n-tree.
+    pub fn summary(&self) -> &A {
+        &self.summary
+    }
+
+    /// Summarize the points within the queried region.
+    ///
+    /// As with `range_count`, regions entirely within the query add
+    /// their cached summaries, and only points in buckets straddling
+    /// the query's boundary are visited.
+    pub fn range_aggregate<Q: Query<R, P> + ?Sized>(&self, query: &Q) -> A {
+        if !query.may_overlap(&self.region) { return A::empty() }
+        if query.covers(&self.region) { return self.summary.clone() }
+
+        match self.kind {
+            Bucket { ref points, .. } => {
+                points
+                    .iter()
+                    .filter(|p| query.matches(p))
+                    .fold(A::empty(), |summary, p| summary.combine(&A::of(p)))
+            },
+            Branch { ref subregions, .. } => {
+                subregions
+                    .iter()
+                    .fold(A::empty(), |summary, sub_node| summary.combine(&sub_node.range_aggregate(query)))
+            }
+        }
+    }
+
+    /// Iterate over all the points in the n-tree.
+    pub fn iter<'t>(&'t self) -> Iter<'t, R, P, S, A> {
+        Iter::new(self)
+    }
+
+    /// Iterate mutably over all the points in the n-tree.
+    ///
+    /// Points are left in the bucket they were in, so they must not
+    /// be changed in a way that would move them to a different bucket.
+    /// Summaries aren't updated either, so anything they summarize
+    /// should be changed through `relocate` instead.
+    pub fn iter_mut<'t>(&'t mut self) -> IterMut<'t, R, P, S, A> {
+        IterMut::new(self)
+    }
+
+    /// Remove all the points from the n-tree, returning them as an
+    /// iterator.
+    ///
+    /// The n-tree is left as a single empty bucket, which splits again
+    /// as new points are inserted.
+    pub fn drain(&mut self) -> IntoIter<R, P, S, A> {
+        let (policy, depth) = self.policy();
+        let kind = mem::replace(&mut self.kind, Bucket { points: vec![], policy, depth });
+        let summary = mem::replace(&mut self.summary, A::empty());
+        IntoIter::new(NTree { region: self.region.clone(), kind, summary })
+    }
+
+    // The policy of this node and its depth, were it a bucket.
+    fn policy(&self) -> (S, usize) {
+        let mut levels = 0;
+        let mut node = self;
+        loop {
+            match node.kind {
+                Bucket { ref policy, depth, .. } => return (policy.clone(), depth - levels),
+                Branch { ref subregions, .. } => {
+                    node = &subregions[0];
+                    levels += 1;
+                }
+            }
+        }
+    }
+
+    /// Gather statistics about the shape of the n-tree.
+    ///
+    /// This walks every node, so it takes time proportional to the
+    /// size of the tree.
+    pub fn stats(&self) -> Stats {
+        Stats::of(self)
+    }
+
     /// Is the point contained in the n-tree?
     pub fn contains(&self, point: &P) -> bool {
         self.region.contains(point)
@@ -162,62 +475,370 @@ impl<P:PartialEq, R: Region<P>> NTree<R, P> {
 
     /// Get all the points nearby a specified point.
     ///
-    /// This will return no more than bucket_limit points.
+    /// This returns the points of the bucket containing the point, so
+    /// its size is bounded by the split policy.
     pub fn nearby<'a>(&'a self, point: &P) -> Option<&'a[P]> {
-        if self.region.contains(point) {
-            match self.kind {
-                Bucket { ref points, .. } => Some(points.as_slice()),
-                Branch { ref subregions } => {
-                    subregions
-                        .iter()
-                        .find(|r| r.contains(point))
-                        .and_then(|r| r.nearby(point))
-                }
-            }
-        } else {
-            None
+        self.bucket_by(&|region: &R| region.contains(point))
+    }
+
+    // Find the bucket reached by following route down the tree.
+    pub(crate) fn bucket_by<F: Fn(&R) -> bool>(&self, route: &F) -> Option<&[P]> {
+        if !route(&self.region) { return None }
+
+        let mut current_node = self;
+        while let Branch { ref subregions, .. } = current_node.kind {
+            current_node = subregions
+                .iter()
+                .find(|sub_node| route(&sub_node.region))
+                .unwrap(); //does always exist, due to invariant of R.split()
+        }
+
+        match current_node.kind {
+            Bucket { ref points, .. } => Some(points.as_slice()),
+            Branch { .. } => unreachable!()
+        }
+    }
+
+    // Mutable access to the bucket reached by following route. The
+    // points must stay where route would find them.
+    pub(crate) fn bucket_by_mut<F: Fn(&R) -> bool>(&mut self, route: &F) -> Option<&mut [P]> {
+        if !route(&self.region) { return None }
+
+        let mut current_node = self;
+        while let Branch { ref mut subregions, .. } = current_node.kind {
+            current_node = subregions
+                .iter_mut()
+                .find(|sub_node| route(&sub_node.region))
+                .unwrap(); //does always exist, due to invariant of R.split()
+        }
+
+        match current_node.kind {
+            Bucket { ref mut points, .. } => Some(points.as_mut_slice()),
+            Branch { .. } => unreachable!()
         }
     }
+
+    /// Get all the points in the n-tree ordered by their distance
+    /// to a specified point, as measured by the metric.
+    ///
+    /// Unlike `nearby`, this is not limited to the bucket containing
+    /// the point: regions are visited closest-first and skipped entirely
+    /// once they are further away than the points already found.
+    pub fn nearest_neighbors<'t, 'p, 'm, M>(&'t self, point: &'p P, metric: &'m M)
+                                            -> NearestNeighbors<'t, 'p, 'm, R, P, M, S, A>
+    where M: Metric<P, R> {
+        NearestNeighbors::new(self, point, metric)
+    }
+
+    /// Get all the points within a distance of a specified point,
+    /// as measured by the metric.
+    ///
+    /// Regions whose lower-bound distance to the point exceeds the
+    /// radius are skipped; points on the boundary are included.
+    pub fn within_radius<'t, 'p, 'm, M>(&'t self, point: &'p P, radius: f64, metric: &'m M)
+                                        -> RadiusQuery<'t, 'p, 'm, R, P, M, S, A>
+    where M: Metric<P, R> {
+        RadiusQuery::new(self, point, radius, metric)
+    }
+
+    /// Get the k points closest to a specified point, nearest first.
+    ///
+    /// Returns fewer than k points if the n-tree holds fewer than k.
+    pub fn k_nearest<'a, M: Metric<P, R>>(&'a self, point: &P, k: usize, metric: &M) -> Vec<&'a P> {
+        self.nearest_neighbors(point, metric).take(k).collect()
+    }
+
+    /// Walk the n-tree as seen from a target point, as the Barnes–Hut
+    /// algorithm does, getting far away nodes whole and nearby points
+    /// one at a time.
+    ///
+    /// A node is approximated by its summary when its widest side is
+    /// less than `theta` times the distance, as measured by the metric,
+    /// from the target to the summary's center. Otherwise it is opened,
+    /// as is any node containing the target. A theta of zero opens every
+    /// node, and larger thetas trade accuracy for fewer nodes and points.
+    ///
+    /// Every point in the n-tree is accounted for exactly once, either
+    /// on its own or within a node, including the target itself if it
+    /// is in the n-tree.
+    pub fn approximate<'t, 'p, 'm, M>(&'t self, target: &'p P, theta: f64, metric: &'m M)
+                                      -> Approximations<'t, 'p, 'm, R, P, M, S, A>
+    where R: Bounds, P: Coordinates, M: Metric<P, R>, A: Centered<P> {
+        Approximations::new(self, target, theta, metric)
+    }
+}
+
+impl<R, P: PartialEq, S, A: Summary<P>> NTree<R, P, S, A> {
+    // Recompute this node's summary from its points or the summaries
+    // of its children, after one of them has changed.
+    fn resummarize(&mut self) {
+        self.summary = match self.kind {
+            Bucket { ref points, .. } => summarize(points),
+            Branch { ref subregions, .. } => {
+                subregions
+                    .iter()
+                    .fold(A::empty(), |summary, sub_node| summary.combine(&sub_node.summary))
+            }
+        };
+    }
 }
 
-fn split_and_insert<P:PartialEq, R: Region<P>>(bucket: &mut NTree<R, P>, point: P) {
-    let old_points;
-    let old_bucket_limit;
+impl<R, P: PartialEq, S, A> IntoIterator for NTree<R, P, S, A> {
+    type Item = P;
+    type IntoIter = IntoIter<R, P, S, A>;
+
+    fn into_iter(self) -> IntoIter<R, P, S, A> {
+        IntoIter::new(self)
+    }
+}
+
+impl<'t, R, P: PartialEq, S, A> IntoIterator for &'t NTree<R, P, S, A> {
+    type Item = &'t P;
+    type IntoIter = Iter<'t, R, P, S, A>;
+
+    fn into_iter(self) -> Iter<'t, R, P, S, A> {
+        Iter::new(self)
+    }
+}
+
+impl<'t, R, P: PartialEq, S, A> IntoIterator for &'t mut NTree<R, P, S, A> {
+    type Item = &'t mut P;
+    type IntoIter = IterMut<'t, R, P, S, A>;
+
+    fn into_iter(self) -> IterMut<'t, R, P, S, A> {
+        IterMut::new(self)
+    }
+}
+
+impl<P:PartialEq, R: Region<P>, S: SplitPolicy<R>, A: Summary<P>> Extend<P> for NTree<R, P, S, A> {
+    /// Insert all the points which lie within the n-tree, partitioning
+    /// them down the tree together rather than one at a time.
+    fn extend<I: IntoIterator<Item=P>>(&mut self, points: I) {
+        let points = points
+            .into_iter()
+            .filter(|point| self.region.contains(point))
+            .collect();
+        insert_all(self, points);
+    }
+}
+
+fn split_and_insert<P:PartialEq, R: Region<P>, S: SplitPolicy<R>, A: Summary<P>>(bucket: &mut NTree<R, P, S, A>, point: P) {
+    let mut old_points;
+    let old_policy;
+    let old_depth;
 
     match bucket.kind {
-        // Get the old region, points, and bucket limit.
-        Bucket { ref mut points, bucket_limit } => {
-            old_points = mem::replace(points, vec![]);
-            old_bucket_limit = bucket_limit;
+        // Get the old region, points, and policy.
+        Bucket { ref mut points, ref policy, depth } => {
+            old_points = mem::take(points);
+            old_policy = policy.clone();
+            old_depth = depth;
         },
         Branch { .. } => unreachable!()
     }
 
     // Replace the bucket with a split branch.
-    *bucket = NTree::new(bucket.region.clone(), old_bucket_limit);
+    *bucket = NTree::branch(bucket.region.clone(), &old_policy, old_depth);
+
+    // Insert all the old points and the new point into the right place.
+    old_points.push(point);
+    insert_all(bucket, old_points);
+}
+
+// Insert many points, all of which are contained in the node's region.
+fn insert_all<P:PartialEq, R: Region<P>, S: SplitPolicy<R>, A: Summary<P>>(node: &mut NTree<R, P, S, A>, mut new_points: Vec<P>) {
+    match node.kind {
+        Bucket { ref mut points, ref policy, depth } => {
+            if !policy.should_split(&node.region, depth, points.len() + new_points.len()) {
+                node.summary = node.summary.combine(&summarize(&new_points));
+                if points.is_empty() {
+                    *points = new_points;
+                } else {
+                    points.append(&mut new_points);
+                }
+                return;
+            }
+
+            // Too many points for this bucket, so split it and partition
+            // everything among the new sub-regions below.
+            new_points.append(points);
+            *node = NTree::branch(node.region.clone(), policy, depth);
+        },
+        Branch { .. } => {}
+    }
+
+    if let Branch { ref mut subregions, ref mut len } = node.kind {
+        *len += new_points.len();
+        node.summary = node.summary.combine(&summarize(&new_points));
+
+        // Find every point's sub-region up front, so each partition
+        // can be allocated once at its final size.
+        let mut sizes = vec![0; subregions.len()];
+        let indices: Vec<usize> = new_points
+            .iter()
+            .map(|point| {
+                let idx = subregions
+                    .iter()
+                    .position(|sub_node| sub_node.region.contains(point))
+                    .unwrap(); //does always exist, due to invariant of R.split()
+                sizes[idx] += 1;
+                idx
+            })
+            .collect();
 
-    // Insert all the old points into the right place.
-    for old_point in old_points.into_iter() {
-        bucket.insert(old_point);
+        let mut partitions: Vec<Vec<P>> = sizes.into_iter().map(Vec::with_capacity).collect();
+        for (point, idx) in new_points.into_iter().zip(indices) {
+            partitions[idx].push(point);
+        }
+
+        for (sub_node, partition) in subregions.iter_mut().zip(partitions) {
+            if !partition.is_empty() {
+                insert_all(sub_node, partition);
+            }
+        }
     }
+}
+
+// The summary of a bucket's worth of points.
+fn summarize<P, A: Summary<P>>(points: &[P]) -> A {
+    points.iter().fold(A::empty(), |summary, point| summary.combine(&A::of(point)))
+}
 
-    // Finally, insert the new point.
-    bucket.insert(point);
+// The outcome of moving a point within a subtree.
+enum Relocation<P> {
+    // The point was moved to its new position within the subtree.
+    Done,
+    // The old point isn't in the subtree.
+    NotFound,
+    // The old point was removed, but its new position is routed outside
+    // the subtree, so it still needs to be inserted.
+    Escaped(P)
+}
+
+// `routed` is whether routing from the root would take the new position
+// to this node. Containing it isn't enough, as a point on an edge shared
+// with an earlier sibling belongs to that sibling instead.
+fn relocate_within<P, R, S, A>(node: &mut NTree<R, P, S, A>, old: &P, new: P, routed: bool) -> Relocation<P>
+where P: PartialEq, R: Region<P>, S: SplitPolicy<R>, A: Summary<P> {
+    let relocation = match node.kind {
+        Bucket { ref mut points, .. } => {
+            let relocation = match points.iter().position(|x| x == old) {
+                None => return Relocation::NotFound,
+                Some(idx) => {
+                    if routed {
+                        points[idx] = new;
+                        Relocation::Done
+                    } else {
+                        points.swap_remove(idx);
+                        Relocation::Escaped(new)
+                    }
+                }
+            };
+            node.resummarize();
+            return relocation
+        },
+        Branch { ref mut subregions, ref mut len } => {
+            let idx = subregions
+                .iter()
+                .position(|sub_node| sub_node.region.contains(old))
+                .unwrap(); //does always exist, due to invariant of R.split()
+            let routed = routed && subregions
+                .iter()
+                .position(|sub_node| sub_node.region.contains(&new)) == Some(idx);
+            let relocation = relocate_within(&mut subregions[idx], old, new, routed);
+            if let Relocation::Escaped(_) = relocation { *len -= 1 }
+            relocation
+        }
+    };
+
+    if let Relocation::NotFound = relocation { return relocation }
+    node.resummarize();
+
+    match relocation {
+        Relocation::Escaped(new) => {
+            if routed {
+                node.insert(new);
+                Relocation::Done
+            } else {
+                merge(node);
+                Relocation::Escaped(new)
+            }
+        },
+        relocation => relocation
+    }
+}
+
+fn merge<P:PartialEq, R: Region<P>, S: SplitPolicy<R>, A: Summary<P>>(branch: &mut NTree<R, P, S, A>) {
+    let policy;
+    let depth;
+
+    match branch.kind {
+        // Only a branch of buckets can be merged: a nested branch is
+        // left for its own children to merge first.
+        Branch { ref subregions, .. } => {
+            let mut total = 0;
+            for sub_node in subregions {
+                match sub_node.kind {
+                    Bucket { ref points, .. } => total += points.len(),
+                    Branch { .. } => return
+                }
+            }
+
+            let (sub_policy, sub_depth) = subregions[0].policy();
+            if !sub_policy.should_merge(&branch.region, sub_depth - 1, total) { return }
+            policy = sub_policy;
+            depth = sub_depth - 1;
+        },
+        Bucket { .. } => unreachable!()
+    }
+
+    // Replace the branch with a bucket of all its points.
+    let points = match branch.kind {
+        Branch { ref mut subregions, .. } => subregions
+            .iter_mut()
+            .flat_map(|sub_node| match sub_node.kind {
+                Bucket { ref mut points, .. } => mem::take(points),
+                Branch { .. } => unreachable!()
+            })
+            .collect(),
+        Bucket { .. } => unreachable!()
+    };
+    branch.kind = Bucket { points, policy, depth };
 }
 
 /// An iterator over the points within a region.
+pub struct RangeQuery<'t,'q, R: 't, P: 't+PartialEq, S: 't = BucketLimit, A: 't = (), Q: 'q + ?Sized = R> {
+    search: Search<'t, R, P, S, A, &'q Q>
+}
+
+impl<'t, 'q, R, P: PartialEq, S, A, Q: Query<R, P> + ?Sized> Iterator for RangeQuery<'t, 'q, R, P, S, A, Q> {
+    type Item = &'t P;
 
+    fn next(&mut self) -> Option<&'t P> {
+        self.search.next()
+    }
+}
+
+// The walk behind `RangeQuery`, over any query it can hold, which lets
+// `NTreeMap` walk its tree with an adapter over its callers' queries.
+//
 // This iterates over the leaves of the tree from left-to-right by
 // maintaining (a) the sequence of points at the current level
 // (possibly empty), and (b) stack of iterators over the remaining
 // children of the parents of the current point.
-pub struct RangeQuery<'t,'q, R: 'q + 't, P: 't+PartialEq> {
-    query: &'q R,
+pub(crate) struct Search<'t, R: 't, P: 't+PartialEq, S: 't, A: 't, H> {
+    query: H,
     points: slice::Iter<'t, P>,
-    stack: Vec<slice::Iter<'t, NTree<R, P>>>
+    stack: Vec<slice::Iter<'t, NTree<R, P, S, A>>>
 }
 
-impl<'t, 'q, R: Region<P>, P: PartialEq> Iterator for RangeQuery<'t, 'q, R, P> {
+impl<'t, R, P: PartialEq, S, A, H> Search<'t, R, P, S, A, H> {
+    pub(crate) fn new(tree: &'t NTree<R, P, S, A>, query: H) -> Search<'t, R, P, S, A, H> {
+        Search { query, points: [].iter(), stack: vec![slice::from_ref(tree).iter()] }
+    }
+}
+
+impl<'t, R, P: PartialEq, S, A, H: HeldQuery<R, P>> Iterator for Search<'t, R, P, S, A, H> {
     type Item = &'t P;
 
     fn next(&mut self) -> Option<&'t P> {
@@ -225,7 +846,7 @@ impl<'t, 'q, R: Region<P>, P: PartialEq> Iterator for RangeQuery<'t, 'q, R, P> {
             // try to find the next point in the region we're
             // currently examining.
             for p in &mut self.points {
-                if self.query.contains(p) {
+                if self.query.matches(p) {
                     return Some(p)
                 }
             }
@@ -233,14 +854,10 @@ impl<'t, 'q, R: Region<P>, P: PartialEq> Iterator for RangeQuery<'t, 'q, R, P> {
             // no relevant points, so lets find a new region.
 
             'region_search: loop {
-                let mut children_iter = match self.stack.pop() {
-                    Some(x) => x,
-
-                    // no more regions, so we're over.
-                    None => return None,
-                };
+                // no more regions, so we're over.
+                let mut children_iter = self.stack.pop()?;
 
-                'children: loop {
+                loop {
                     // look at the next item in the current sequence
                     // of children.
                     match children_iter.next() {
@@ -248,7 +865,7 @@ impl<'t, 'q, R: Region<P>, P: PartialEq> Iterator for RangeQuery<'t, 'q, R, P> {
                         None => continue 'region_search,
 
                         Some(value) => {
-                            if value.region.overlaps(self.query) {
+                            if self.query.may_overlap(&value.region) {
                                 // we always need to save this state, either we
                                 // recur into a new region, or we break out and
                                 // handle the points; either way, this is the
@@ -263,7 +880,7 @@ impl<'t, 'q, R: Region<P>, P: PartialEq> Iterator for RangeQuery<'t, 'q, R, P> {
                                         continue 'outer;
                                     }
                                     // step down into nested regions.
-                                    Branch { ref subregions } => children_iter = subregions.iter()
+                                    Branch { ref subregions, .. } => children_iter = subregions.iter()
                                 }
                             }
                         }
@@ -0,0 +1,173 @@
+//! An n-tree which associates a value with each position.
+
+use std::mem;
+
+use {BucketLimit, NTree, Query, Region, Search};
+use query::HeldQuery;
+
+/// A map from positions to values, indexed by an n-tree.
+///
+/// Each position holds at most one value. Positions only need to be
+/// compared for equality to find their entry, so values can be any
+/// type at all.
+pub struct NTreeMap<R, P: PartialEq, V> {
+    tree: Tree<R, P, V>
+}
+
+type Tree<R, P, V> = NTree<Keyed<R>, Entry<P, V>>;
+
+// Regions of the underlying tree, which route entries by position.
+#[derive(Clone)]
+struct Keyed<R>(R);
+
+// Entries compare equal when their positions do, so the underlying
+// tree never needs to compare values.
+struct Entry<P, V> {
+    position: P,
+    value: V
+}
+
+impl<P: PartialEq, V> PartialEq for Entry<P, V> {
+    fn eq(&self, other: &Entry<P, V>) -> bool {
+        self.position == other.position
+    }
+}
+
+impl<R: Region<P>, P, V> Region<Entry<P, V>> for Keyed<R> {
+    fn contains(&self, entry: &Entry<P, V>) -> bool {
+        self.0.contains(&entry.position)
+    }
+
+    fn split(&self) -> Vec<Keyed<R>> {
+        self.0.split().into_iter().map(Keyed).collect()
+    }
+
+    fn overlaps(&self, other: &Keyed<R>) -> bool {
+        self.0.overlaps(&other.0)
+    }
+
+    fn contains_region(&self, other: &Keyed<R>) -> bool {
+        self.0.contains_region(&other.0)
+    }
+}
+
+// A query over positions, held as a query over the underlying tree's
+// keyed regions and entries, so map range queries walk the tree as
+// `NTree::range_query` does.
+struct ByPosition<'q, Q: 'q + ?Sized>(&'q Q);
+
+impl<'q, R, P, V, Q: Query<R, P> + ?Sized> HeldQuery<Keyed<R>, Entry<P, V>> for ByPosition<'q, Q> {
+    fn may_overlap(&self, region: &Keyed<R>) -> bool {
+        self.0.may_overlap(&region.0)
+    }
+
+    fn matches(&self, entry: &Entry<P, V>) -> bool {
+        self.0.matches(&entry.position)
+    }
+}
+
+impl<R: Region<P>, P: PartialEq, V> NTreeMap<R, P, V> {
+    /// Create a new, empty map over the region, whose buckets are
+    /// limited to the passed-in size.
+    pub fn new(region: R, size: u8) -> NTreeMap<R, P, V> {
+        NTreeMap { tree: NTree::new(Keyed(region), size) }
+    }
+
+    /// Insert a value at a position.
+    ///
+    /// Returns the value previously at the position, if any, or hands
+    /// back the position and value if the position is not within the map.
+    pub fn insert(&mut self, position: P, value: V) -> Result<Option<V>, (P, V)> {
+        if !self.tree.region.0.contains(&position) {
+            return Err((position, value))
+        }
+
+        if let Some(old) = self.get_mut(&position) {
+            return Ok(Some(mem::replace(old, value)))
+        }
+
+        self.tree.insert(Entry { position, value });
+        Ok(None)
+    }
+
+    /// Get the value at a position.
+    pub fn get(&self, position: &P) -> Option<&V> {
+        self.tree
+            .bucket_by(&|region: &Keyed<R>| region.0.contains(position))?
+            .iter()
+            .find(|entry| entry.position == *position)
+            .map(|entry| &entry.value)
+    }
+
+    /// Get mutable access to the value at a position.
+    pub fn get_mut(&mut self, position: &P) -> Option<&mut V> {
+        self.tree
+            .bucket_by_mut(&|region: &Keyed<R>| region.0.contains(position))?
+            .iter_mut()
+            .find(|entry| entry.position == *position)
+            .map(|entry| &mut entry.value)
+    }
+
+    /// Is there a value at the position?
+    pub fn contains_key(&self, position: &P) -> bool {
+        self.get(position).is_some()
+    }
+
+    /// Remove the value at a position, returning it if there was one.
+    pub fn remove(&mut self, position: &P) -> Option<V> {
+        self.tree
+            .remove_by(&|region: &Keyed<R>| region.0.contains(position),
+                       &|entry: &Entry<P, V>| entry.position == *position)
+            .map(|entry| entry.value)
+    }
+
+    /// The number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// Does the map hold no entries?
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Iterate over all the positions and their values.
+    pub fn iter<'t>(&'t self) -> Iter<'t, R, P, V> {
+        Iter { inner: self.tree.iter() }
+    }
+
+    /// Get all the entries whose positions lie within the queried region.
+    ///
+    /// As with `NTree::range_query`, the query can be any shape
+    /// implementing `Query`.
+    pub fn range_query<'t, 'q, Q>(&'t self, query: &'q Q) -> RangeQuery<'t, 'q, R, P, V, Q>
+    where Q: Query<R, P> + ?Sized {
+        RangeQuery { search: Search::new(&self.tree, ByPosition(query)) }
+    }
+}
+
+/// An iterator over all the entries of an `NTreeMap`.
+pub struct Iter<'t, R: 't, P: 't + PartialEq, V: 't> {
+    inner: ::Iter<'t, Keyed<R>, Entry<P, V>>
+}
+
+impl<'t, R, P: PartialEq, V> Iterator for Iter<'t, R, P, V> {
+    type Item = (&'t P, &'t V);
+
+    fn next(&mut self) -> Option<(&'t P, &'t V)> {
+        self.inner.next().map(|entry| (&entry.position, &entry.value))
+    }
+}
+
+/// An iterator over the entries of an `NTreeMap` within a region.
+pub struct RangeQuery<'t, 'q, R: 't, P: 't + PartialEq, V: 't, Q: 'q + ?Sized = R> {
+    search: Search<'t, Keyed<R>, Entry<P, V>, BucketLimit, (), ByPosition<'q, Q>>
+}
+
+impl<'t, 'q, R, P: PartialEq, V, Q: Query<R, P> + ?Sized> Iterator for RangeQuery<'t, 'q, R, P, V, Q> {
+    type Item = (&'t P, &'t V);
+
+    fn next(&mut self) -> Option<(&'t P, &'t V)> {
+        self.search.next().map(|entry| (&entry.position, &entry.value))
+    }
+}
@@ -0,0 +1,550 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::Path;
+
+use crc32fast::Hasher;
+use memmap2::Mmap;
+
+use {Metric, NTree, Query, Region};
+use NTreeVariant::{Branch, Bucket};
+use regions::{BoxRegion, OctreeRegion, Point, QuadTreeRegion, Vec2, Vec3};
+
+// The file format, with all integers little-endian:
+//
+//   header:   magic, version, region size, point size, reserved (all u32
+//             but the 8-byte magic), node count and point count (u64)
+//   nodes:    a tag, the index and number of the node's children or
+//             points (u64), then its region; in breadth-first order, so
+//             the children of every branch are contiguous
+//   points:   the points of every bucket, in the order of the buckets
+//   checksum: the CRC-32 of everything before it (u32)
+const MAGIC: &[u8; 8] = b"NTREEIDX";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 40;
+const NODE_HEADER_SIZE: usize = 17;
+const CHECKSUM_SIZE: usize = 4;
+
+const BUCKET: u8 = 0;
+const BRANCH: u8 = 1;
+
+/// Points and regions with a fixed-size binary encoding, which can be
+/// stored in the files read by `MappedNTree`.
+pub trait FixedSize: Sized {
+    /// The number of bytes in the encoding.
+    const SIZE: usize;
+
+    /// Encode the value into a buffer of exactly `SIZE` bytes.
+    fn encode(&self, buf: &mut [u8]);
+
+    /// Decode a value from a buffer of exactly `SIZE` bytes.
+    fn decode(buf: &[u8]) -> Self;
+}
+
+fn encode_f64s(values: &[f64], buf: &mut [u8]) {
+    for (value, chunk) in values.iter().zip(buf.chunks_mut(8)) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn decode_f64(buf: &[u8], idx: usize) -> f64 {
+    f64::from_le_bytes(read_array(&buf[idx * 8..]))
+}
+
+fn read_array<const N: usize>(buf: &[u8]) -> [u8; N] {
+    let mut array = [0; N];
+    array.copy_from_slice(&buf[..N]);
+    array
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(read_array(&buf[offset..]))
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(read_array(&buf[offset..]))
+}
+
+impl FixedSize for Vec2 {
+    const SIZE: usize = 16;
+
+    fn encode(&self, buf: &mut [u8]) {
+        encode_f64s(&[self.x, self.y], buf)
+    }
+
+    fn decode(buf: &[u8]) -> Vec2 {
+        Vec2 { x: decode_f64(buf, 0), y: decode_f64(buf, 1) }
+    }
+}
+
+impl FixedSize for QuadTreeRegion {
+    const SIZE: usize = 32;
+
+    fn encode(&self, buf: &mut [u8]) {
+        encode_f64s(&[self.x, self.y, self.width, self.height], buf)
+    }
+
+    fn decode(buf: &[u8]) -> QuadTreeRegion {
+        QuadTreeRegion {
+            x: decode_f64(buf, 0),
+            y: decode_f64(buf, 1),
+            width: decode_f64(buf, 2),
+            height: decode_f64(buf, 3)
+        }
+    }
+}
+
+impl FixedSize for Vec3 {
+    const SIZE: usize = 24;
+
+    fn encode(&self, buf: &mut [u8]) {
+        encode_f64s(&[self.x, self.y, self.z], buf)
+    }
+
+    fn decode(buf: &[u8]) -> Vec3 {
+        Vec3 { x: decode_f64(buf, 0), y: decode_f64(buf, 1), z: decode_f64(buf, 2) }
+    }
+}
+
+impl FixedSize for OctreeRegion {
+    const SIZE: usize = 48;
+
+    fn encode(&self, buf: &mut [u8]) {
+        encode_f64s(&[self.x, self.y, self.z, self.width, self.height, self.depth], buf)
+    }
+
+    fn decode(buf: &[u8]) -> OctreeRegion {
+        OctreeRegion {
+            x: decode_f64(buf, 0),
+            y: decode_f64(buf, 1),
+            z: decode_f64(buf, 2),
+            width: decode_f64(buf, 3),
+            height: decode_f64(buf, 4),
+            depth: decode_f64(buf, 5)
+        }
+    }
+}
+
+impl<const N: usize> FixedSize for Point<N> {
+    const SIZE: usize = 8 * N;
+
+    fn encode(&self, buf: &mut [u8]) {
+        encode_f64s(&self.0, buf)
+    }
+
+    fn decode(buf: &[u8]) -> Point<N> {
+        let mut coordinates = [0.0; N];
+        for (axis, x) in coordinates.iter_mut().enumerate() {
+            *x = decode_f64(buf, axis);
+        }
+        Point(coordinates)
+    }
+}
+
+impl<const N: usize> FixedSize for BoxRegion<N> {
+    const SIZE: usize = 16 * N;
+
+    fn encode(&self, buf: &mut [u8]) {
+        let (min, max) = buf.split_at_mut(8 * N);
+        encode_f64s(&self.min, min);
+        encode_f64s(&self.max, max);
+    }
+
+    fn decode(buf: &[u8]) -> BoxRegion<N> {
+        let (min, max) = buf.split_at(8 * N);
+        BoxRegion { min: Point::decode(min).0, max: Point::decode(max).0 }
+    }
+}
+
+// A writer which keeps a running checksum of everything written.
+struct Checksummed<W: Write> {
+    inner: BufWriter<W>,
+    hasher: Hasher
+}
+
+impl<W: Write> Checksummed<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.hasher.update(bytes);
+        self.inner.write_all(bytes)
+    }
+}
+
+impl<R: FixedSize, P: PartialEq + FixedSize, S, A> NTree<R, P, S, A> {
+    /// Write the n-tree in the binary format read by `MappedNTree`.
+    ///
+    /// The split policy is not written, since mapped n-trees are
+    /// read-only.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        // Number the nodes breadth-first.
+        let mut order = vec![self];
+        let mut idx = 0;
+        while idx < order.len() {
+            if let Branch { ref subregions, .. } = order[idx].kind {
+                order.extend(subregions);
+            }
+            idx += 1;
+        }
+
+        let points: usize = order
+            .iter()
+            .map(|node| match node.kind {
+                Bucket { ref points, .. } => points.len(),
+                Branch { .. } => 0
+            })
+            .sum();
+
+        let mut out = Checksummed { inner: BufWriter::new(writer), hasher: Hasher::new() };
+        out.write(MAGIC)?;
+        for &field in &[VERSION, R::SIZE as u32, P::SIZE as u32, 0] {
+            out.write(&field.to_le_bytes())?;
+        }
+        out.write(&(order.len() as u64).to_le_bytes())?;
+        out.write(&(points as u64).to_le_bytes())?;
+
+        let mut record = vec![0; NODE_HEADER_SIZE + R::SIZE];
+        let mut next_node = 1;
+        let mut next_point = 0;
+        for node in &order {
+            let (tag, first, count) = match node.kind {
+                Bucket { ref points, .. } => {
+                    next_point += points.len();
+                    (BUCKET, next_point - points.len(), points.len())
+                },
+                Branch { ref subregions, .. } => {
+                    next_node += subregions.len();
+                    (BRANCH, next_node - subregions.len(), subregions.len())
+                }
+            };
+
+            record[0] = tag;
+            record[1..9].copy_from_slice(&(first as u64).to_le_bytes());
+            record[9..17].copy_from_slice(&(count as u64).to_le_bytes());
+            node.region.encode(&mut record[NODE_HEADER_SIZE..]);
+            out.write(&record)?;
+        }
+
+        let mut buf = vec![0; P::SIZE];
+        for node in &order {
+            if let Bucket { ref points, .. } = node.kind {
+                for point in points {
+                    point.encode(&mut buf);
+                    out.write(&buf)?;
+                }
+            }
+        }
+
+        let checksum = out.hasher.clone().finalize();
+        out.inner.write_all(&checksum.to_le_bytes())?;
+        out.inner.flush()
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A read-only n-tree queried directly from a memory-mapped file, as
+/// written by `NTree::write_to`.
+///
+/// Nothing is deserialized up front: nodes and points are decoded from
+/// the mapping as queries reach them, so queries yield points by value.
+pub struct MappedNTree<R, P> {
+    map: Mmap,
+    nodes: usize,
+    points: usize,
+    marker: PhantomData<fn() -> (R, P)>
+}
+
+// A decoded node record.
+struct Node<R> {
+    region: R,
+    branch: bool,
+    // The node's children, or its points.
+    range: Range<usize>
+}
+
+impl<R: Region<P> + FixedSize, P: FixedSize> MappedNTree<R, P> {
+    /// Map an n-tree file, checking its version, checksum and structure.
+    ///
+    /// Checking the checksum reads the whole file once. The file must
+    /// not be modified while it is mapped.
+    pub fn open<Q: AsRef<Path>>(path: Q) -> io::Result<MappedNTree<R, P>> {
+        let file = File::open(path)?;
+        // The mapping is only ever read; modifying the file underneath
+        // it is ruled out by the documented contract above.
+        let map = unsafe { Mmap::map(&file)? };
+
+        if map.len() < HEADER_SIZE + CHECKSUM_SIZE || &map[..8] != MAGIC {
+            return Err(invalid("not an n-tree file"))
+        }
+        if read_u32(&map, 8) != VERSION {
+            return Err(invalid("unsupported n-tree file version"))
+        }
+        if read_u32(&map, 12) as usize != R::SIZE || read_u32(&map, 16) as usize != P::SIZE {
+            return Err(invalid("n-tree file has a different region or point type"))
+        }
+
+        let nodes = read_u64(&map, 24);
+        let points = read_u64(&map, 32);
+        let size = (NODE_HEADER_SIZE + R::SIZE) as u64;
+        let expected = nodes.checked_mul(size)
+            .and_then(|len| points.checked_mul(P::SIZE as u64)?.checked_add(len))
+            .and_then(|len| len.checked_add((HEADER_SIZE + CHECKSUM_SIZE) as u64));
+        if nodes == 0 || expected != Some(map.len() as u64) {
+            return Err(invalid("n-tree file has the wrong length"))
+        }
+
+        let body = map.len() - CHECKSUM_SIZE;
+        let mut hasher = Hasher::new();
+        hasher.update(&map[..body]);
+        if hasher.finalize() != read_u32(&map, body) {
+            return Err(invalid("n-tree file checksum does not match"))
+        }
+
+        let tree = MappedNTree { map, nodes: nodes as usize, points: points as usize, marker: PhantomData };
+        tree.check_structure()?;
+        Ok(tree)
+    }
+
+    // Make sure every node refers to points and children which exist,
+    // and that children always come after their parent, so queries
+    // can neither read out of bounds nor loop forever.
+    fn check_structure(&self) -> io::Result<()> {
+        for idx in 0..self.nodes {
+            let record = self.record(idx);
+            let first = read_u64(record, 1);
+            let count = read_u64(record, 9);
+            let end = first.checked_add(count);
+
+            let valid = match record[0] {
+                BUCKET => end.map_or(false, |end| end <= self.points as u64),
+                BRANCH => count > 0 && first > idx as u64 && end.map_or(false, |end| end <= self.nodes as u64),
+                _ => false
+            };
+            if !valid {
+                return Err(invalid("n-tree file has a malformed node"))
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&self, idx: usize) -> &[u8] {
+        let size = NODE_HEADER_SIZE + R::SIZE;
+        let start = HEADER_SIZE + idx * size;
+        &self.map[start..start + size]
+    }
+
+    fn node(&self, idx: usize) -> Node<R> {
+        let record = self.record(idx);
+        let first = read_u64(record, 1) as usize;
+        let count = read_u64(record, 9) as usize;
+        Node {
+            region: R::decode(&record[NODE_HEADER_SIZE..]),
+            branch: record[0] == BRANCH,
+            range: first..first + count
+        }
+    }
+
+    fn point(&self, idx: usize) -> P {
+        let start = HEADER_SIZE + self.nodes * (NODE_HEADER_SIZE + R::SIZE) + idx * P::SIZE;
+        P::decode(&self.map[start..start + P::SIZE])
+    }
+
+    /// The number of points in the n-tree.
+    pub fn len(&self) -> usize {
+        self.points
+    }
+
+    /// Does the n-tree hold no points?
+    pub fn is_empty(&self) -> bool {
+        self.points == 0
+    }
+
+    /// Is the point contained in the n-tree?
+    pub fn contains(&self, point: &P) -> bool {
+        self.node(0).region.contains(point)
+    }
+
+    /// Get all the points within the queried region, as
+    /// `NTree::range_query` does.
+    ///
+    /// The query can be any shape implementing `Query`.
+    pub fn range_query<'t, 'q, Q>(&'t self, query: &'q Q) -> MappedRangeQuery<'t, 'q, R, P, Q>
+    where Q: Query<R, P> + ?Sized {
+        // the root is node zero.
+        MappedRangeQuery { tree: self, query, points: 0..0, stack: vec![Range { start: 0, end: 1 }] }
+    }
+
+    /// Get all the points in the bucket containing a specified point,
+    /// as `NTree::nearby` does.
+    pub fn nearby<'t>(&'t self, point: &P) -> Option<MappedPoints<'t, R, P>> {
+        let mut node = self.node(0);
+        if !node.region.contains(point) { return None }
+
+        while node.branch {
+            node = node.range
+                .map(|idx| self.node(idx))
+                .find(|sub_node| sub_node.region.contains(point))?;
+        }
+        Some(MappedPoints { tree: self, range: node.range })
+    }
+
+    /// Get all the points in the n-tree ordered by their distance to a
+    /// specified point, as `NTree::nearest_neighbors` does.
+    pub fn nearest_neighbors<'t, 'p, 'm, M>(&'t self, point: &'p P, metric: &'m M)
+                                            -> MappedNearestNeighbors<'t, 'p, 'm, R, P, M>
+    where M: Metric<P, R> {
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: metric.min_distance(point, &self.node(0).region),
+            item: Item::Node(0)
+        });
+
+        MappedNearestNeighbors { tree: self, point, metric, queue }
+    }
+
+    /// Get the k points closest to a specified point, nearest first.
+    pub fn k_nearest<M: Metric<P, R>>(&self, point: &P, k: usize, metric: &M) -> Vec<P> {
+        self.nearest_neighbors(point, metric).take(k).collect()
+    }
+}
+
+/// An iterator over the points of one bucket of a `MappedNTree`.
+pub struct MappedPoints<'t, R: 't, P: 't> {
+    tree: &'t MappedNTree<R, P>,
+    range: Range<usize>
+}
+
+impl<'t, R: Region<P> + FixedSize, P: FixedSize> Iterator for MappedPoints<'t, R, P> {
+    type Item = P;
+
+    fn next(&mut self) -> Option<P> {
+        self.range.next().map(|idx| self.tree.point(idx))
+    }
+}
+
+/// An iterator over the points of a `MappedNTree` within a region.
+//
+// This is `RangeQuery`, with ranges of node and point indices standing
+// in for slice iterators. Children are numbered contiguously, so each
+// level of the stack is just the range of siblings left to examine.
+pub struct MappedRangeQuery<'t, 'q, R: 't, P: 't, Q: 'q + ?Sized = R> {
+    tree: &'t MappedNTree<R, P>,
+    query: &'q Q,
+    points: Range<usize>,
+    stack: Vec<Range<usize>>
+}
+
+impl<'t, 'q, R, P, Q> Iterator for MappedRangeQuery<'t, 'q, R, P, Q>
+where R: Region<P> + FixedSize, P: FixedSize, Q: Query<R, P> + ?Sized {
+    type Item = P;
+
+    fn next(&mut self) -> Option<P> {
+        loop {
+            for idx in &mut self.points {
+                let point = self.tree.point(idx);
+                if self.query.matches(&point) {
+                    return Some(point)
+                }
+            }
+
+            // find the next overlapping node, dropping exhausted levels.
+            let node = loop {
+                match self.stack.last_mut()?.next() {
+                    Some(idx) => {
+                        let node = self.tree.node(idx);
+                        if self.query.may_overlap(&node.region) { break node }
+                    },
+                    None => { self.stack.pop(); }
+                }
+            };
+
+            if node.branch {
+                self.stack.push(node.range);
+            } else {
+                self.points = node.range;
+            }
+        }
+    }
+}
+
+/// An iterator over the points of a `MappedNTree` in increasing
+/// distance from a target point.
+//
+// The same best-first traversal as `NearestNeighbors`.
+pub struct MappedNearestNeighbors<'t, 'p, 'm, R: 't, P: 't + 'p, M: 'm> {
+    tree: &'t MappedNTree<R, P>,
+    point: &'p P,
+    metric: &'m M,
+    queue: BinaryHeap<Candidate<P>>
+}
+
+impl<'t, 'p, 'm, R, P, M> Iterator for MappedNearestNeighbors<'t, 'p, 'm, R, P, M>
+where R: Region<P> + FixedSize, P: FixedSize, M: Metric<P, R> {
+    type Item = P;
+
+    fn next(&mut self) -> Option<P> {
+        loop {
+            match self.queue.pop()?.item {
+                Item::Point(p) => return Some(p),
+                Item::Node(idx) => {
+                    let node = self.tree.node(idx);
+                    for idx in node.range {
+                        let candidate = if node.branch {
+                            Candidate {
+                                distance: self.metric.min_distance(self.point, &self.tree.node(idx).region),
+                                item: Item::Node(idx)
+                            }
+                        } else {
+                            let p = self.tree.point(idx);
+                            Candidate { distance: self.metric.distance(self.point, &p), item: Item::Point(p) }
+                        };
+                        self.queue.push(candidate);
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum Item<P> {
+    Node(usize),
+    Point(P)
+}
+
+struct Candidate<P> {
+    distance: f64,
+    item: Item<P>
+}
+
+// Reversed for the max-heap, with points before nodes on ties, as in
+// `NearestNeighbors`.
+impl<P> Ord for Candidate<P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance
+            .partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| match (&self.item, &other.item) {
+                (&Item::Point(_), &Item::Node(_)) => Ordering::Greater,
+                (&Item::Node(_), &Item::Point(_)) => Ordering::Less,
+                _ => Ordering::Equal
+            })
+    }
+}
+
+impl<P> PartialOrd for Candidate<P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P> PartialEq for Candidate<P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<P> Eq for Candidate<P> {}
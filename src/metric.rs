@@ -0,0 +1,103 @@
+//! Distance functions used by the neighbor queries.
+
+/// A distance function over points, paired with a lower bound on the
+/// distance from a point to anything inside a region.
+///
+/// Neighbor searches use `min_distance` to decide which parts of the
+/// tree can be skipped, so it must never over-estimate: for every point
+/// `q` contained in `region`, `min_distance(p, region) <= distance(p, q)`
+/// must hold.
+pub trait Metric<P, R> {
+    /// The distance between two points.
+    fn distance(&self, a: &P, b: &P) -> f64;
+
+    /// The smallest possible distance from the point to any point
+    /// contained in the region. Should be zero if the region contains
+    /// the point.
+    fn min_distance(&self, point: &P, region: &R) -> f64;
+}
+
+/// Points with a fixed number of real-valued coordinates.
+///
+/// Implementing this for a point type, and `Bounds` for its region type,
+/// makes the built-in metrics available for that pair.
+pub trait Coordinates {
+    /// The number of axes this point has.
+    fn dimensions(&self) -> usize;
+
+    /// The coordinate of this point along an axis, `0 <= axis < dimensions()`.
+    fn coordinate(&self, axis: usize) -> f64;
+}
+
+/// Regions which are axis-aligned boxes.
+pub trait Bounds {
+    /// The smallest coordinate along an axis contained in the region.
+    fn lower(&self, axis: usize) -> f64;
+
+    /// The largest coordinate along an axis contained in the region.
+    fn upper(&self, axis: usize) -> f64;
+}
+
+/// The straight-line (L2) distance.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Euclidean;
+
+/// The taxicab (L1) distance, summing the distance along each axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Manhattan;
+
+/// The chessboard (L-infinity) distance, taking the largest distance
+/// along any one axis.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Chebyshev;
+
+impl<P: Coordinates, R: Bounds> Metric<P, R> for Euclidean {
+    fn distance(&self, a: &P, b: &P) -> f64 {
+        (0..a.dimensions())
+            .map(|axis| (a.coordinate(axis) - b.coordinate(axis)).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    fn min_distance(&self, point: &P, region: &R) -> f64 {
+        (0..point.dimensions())
+            .map(|axis| gap(point, region, axis).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+}
+
+impl<P: Coordinates, R: Bounds> Metric<P, R> for Manhattan {
+    fn distance(&self, a: &P, b: &P) -> f64 {
+        (0..a.dimensions())
+            .map(|axis| (a.coordinate(axis) - b.coordinate(axis)).abs())
+            .sum()
+    }
+
+    fn min_distance(&self, point: &P, region: &R) -> f64 {
+        (0..point.dimensions())
+            .map(|axis| gap(point, region, axis))
+            .sum()
+    }
+}
+
+impl<P: Coordinates, R: Bounds> Metric<P, R> for Chebyshev {
+    fn distance(&self, a: &P, b: &P) -> f64 {
+        (0..a.dimensions())
+            .map(|axis| (a.coordinate(axis) - b.coordinate(axis)).abs())
+            .fold(0.0, f64::max)
+    }
+
+    fn min_distance(&self, point: &P, region: &R) -> f64 {
+        (0..point.dimensions())
+            .map(|axis| gap(point, region, axis))
+            .fold(0.0, f64::max)
+    }
+}
+
+// How far the point lies outside the region along one axis, or zero
+// if its coordinate is within the region's extent on that axis.
+fn gap<P: Coordinates, R: Bounds>(point: &P, region: &R, axis: usize) -> f64 {
+    let x = point.coordinate(axis);
+    (region.lower(axis) - x).max(x - region.upper(axis)).max(0.0)
+}
@@ -0,0 +1,171 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::slice;
+
+use {BucketLimit, Metric, NTree, Region};
+use NTreeVariant::{Branch, Bucket};
+
+/// An iterator over the points of an n-tree in increasing distance
+/// from a target point.
+//
+// This is a best-first traversal: nodes and points share a single
+// priority queue keyed on their (lower bound) distance to the target.
+// A node is only expanded once nothing in the queue is closer than it,
+// so any point popped off the queue is guaranteed to be no further
+// than everything still waiting to be examined.
+pub struct NearestNeighbors<'t, 'p, 'm, R: 't, P: 't + 'p + PartialEq, M: 'm, S: 't = BucketLimit, A: 't = ()> {
+    point: &'p P,
+    metric: &'m M,
+    queue: BinaryHeap<Candidate<'t, R, P, S, A>>
+}
+
+impl<'t, 'p, 'm, R, P, M, S, A> NearestNeighbors<'t, 'p, 'm, R, P, M, S, A>
+where R: Region<P>, P: PartialEq, M: Metric<P, R> {
+    pub(crate) fn new(tree: &'t NTree<R, P, S, A>, point: &'p P, metric: &'m M)
+                      -> NearestNeighbors<'t, 'p, 'm, R, P, M, S, A> {
+        let mut queue = BinaryHeap::new();
+        queue.push(Candidate {
+            distance: metric.min_distance(point, &tree.region),
+            item: Item::Node(tree)
+        });
+
+        NearestNeighbors { point, metric, queue }
+    }
+}
+
+impl<'t, 'p, 'm, R, P, M, S, A> Iterator for NearestNeighbors<'t, 'p, 'm, R, P, M, S, A>
+where R: Region<P>, P: PartialEq, M: Metric<P, R> {
+    type Item = &'t P;
+
+    fn next(&mut self) -> Option<&'t P> {
+        loop {
+            match self.queue.pop()?.item {
+                Item::Point(p) => return Some(p),
+                Item::Node(node) => match node.kind {
+                    Bucket { ref points, .. } => {
+                        for p in points {
+                            self.queue.push(Candidate {
+                                distance: self.metric.distance(self.point, p),
+                                item: Item::Point(p)
+                            });
+                        }
+                    },
+                    Branch { ref subregions, .. } => {
+                        for sub in subregions {
+                            self.queue.push(Candidate {
+                                distance: self.metric.min_distance(self.point, &sub.region),
+                                item: Item::Node(sub)
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// An iterator over the points within a distance of a target point.
+//
+// This walks the tree exactly like `RangeQuery`, except that a region
+// is only descended into if its lower-bound distance to the target is
+// within the radius.
+pub struct RadiusQuery<'t, 'p, 'm, R: 't, P: 't + 'p + PartialEq, M: 'm, S: 't = BucketLimit, A: 't = ()> {
+    point: &'p P,
+    radius: f64,
+    metric: &'m M,
+    points: slice::Iter<'t, P>,
+    stack: Vec<slice::Iter<'t, NTree<R, P, S, A>>>
+}
+
+impl<'t, 'p, 'm, R, P, M, S, A> RadiusQuery<'t, 'p, 'm, R, P, M, S, A>
+where R: Region<P>, P: PartialEq, M: Metric<P, R> {
+    pub(crate) fn new(tree: &'t NTree<R, P, S, A>, point: &'p P, radius: f64, metric: &'m M)
+                      -> RadiusQuery<'t, 'p, 'm, R, P, M, S, A> {
+        RadiusQuery {
+            point,
+            radius,
+            metric,
+            points: [].iter(),
+            stack: vec![slice::from_ref(tree).iter()]
+        }
+    }
+}
+
+impl<'t, 'p, 'm, R, P, M, S, A> Iterator for RadiusQuery<'t, 'p, 'm, R, P, M, S, A>
+where R: Region<P>, P: PartialEq, M: Metric<P, R> {
+    type Item = &'t P;
+
+    fn next(&mut self) -> Option<&'t P> {
+        'outer: loop {
+            for p in &mut self.points {
+                if self.metric.distance(self.point, p) <= self.radius {
+                    return Some(p)
+                }
+            }
+
+            'region_search: loop {
+                let mut children_iter = self.stack.pop()?;
+
+                loop {
+                    match children_iter.next() {
+                        None => continue 'region_search,
+
+                        Some(value) => {
+                            if self.metric.min_distance(self.point, &value.region) <= self.radius {
+                                self.stack.push(children_iter);
+
+                                match value.kind {
+                                    Bucket { ref points, .. } => {
+                                        self.points = points.iter();
+                                        continue 'outer;
+                                    }
+                                    Branch { ref subregions, .. } => children_iter = subregions.iter()
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum Item<'t, R: 't, P: 't + PartialEq, S: 't, A: 't> {
+    Node(&'t NTree<R, P, S, A>),
+    Point(&'t P)
+}
+
+struct Candidate<'t, R: 't, P: 't + PartialEq, S: 't, A: 't> {
+    distance: f64,
+    item: Item<'t, R, P, S, A>
+}
+
+// BinaryHeap is a max-heap, so candidates are ordered by reversed
+// distance to pop the closest one first. On ties, points come out
+// before nodes so that a point is never held back by an empty region.
+impl<'t, R, P: PartialEq, S, A> Ord for Candidate<'t, R, P, S, A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance
+            .partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| match (&self.item, &other.item) {
+                (&Item::Point(_), &Item::Node(_)) => Ordering::Greater,
+                (&Item::Node(_), &Item::Point(_)) => Ordering::Less,
+                _ => Ordering::Equal
+            })
+    }
+}
+
+impl<'t, R, P: PartialEq, S, A> PartialOrd for Candidate<'t, R, P, S, A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'t, R, P: PartialEq, S, A> PartialEq for Candidate<'t, R, P, S, A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'t, R, P: PartialEq, S, A> Eq for Candidate<'t, R, P, S, A> {}
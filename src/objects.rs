@@ -0,0 +1,278 @@
+use std::marker::PhantomData;
+use std::{mem, slice};
+
+use {Region, DEFAULT_MAX_DEPTH};
+use self::ObjectVariant::{Branch, Bucket};
+
+/// Objects with extent, which can report a region bounding them.
+pub trait Bounded<R> {
+    /// A region containing the whole object.
+    fn bounds(&self) -> R;
+}
+
+/// An n-tree of objects with extent, such as sprites or colliders,
+/// rather than of points.
+///
+/// Each object is stored at the deepest node whose region entirely
+/// contains its bounds, so an object straddling the boundary between
+/// sub-regions stays with their parent. Objects which are only partly
+/// within the tree's region are kept at the root.
+///
+/// This relies on `Region::contains_region`; with its default, every
+/// object stays at the root.
+pub struct ObjectTree<R, P, T> {
+    region: R,
+    // In a bucket, all the objects within its region. In a branch, the
+    // objects which don't fit in any one of its sub-regions.
+    objects: Vec<T>,
+    kind: ObjectVariant<R, P, T>,
+    point: PhantomData<fn(&P)>
+}
+
+enum ObjectVariant<R, P, T> {
+    /// A leaf of the tree.
+    Bucket {
+        bucket_limit: u8,
+        splits_left: u8
+    },
+    /// An interior node of the tree, which contains n subtrees.
+    Branch {
+        subregions: Vec<ObjectTree<R, P, T>>
+    }
+}
+
+impl<R: Region<P>, P, T: Bounded<R>> ObjectTree<R, P, T> {
+    /// Create a new object tree over the region, whose buckets are
+    /// limited to the passed-in size.
+    ///
+    /// The tree is limited to a depth of `DEFAULT_MAX_DEPTH`.
+    pub fn new(region: R, size: u8) -> ObjectTree<R, P, T> {
+        ObjectTree::with_max_depth(region, size, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Create a new object tree over the region, whose buckets are
+    /// limited to the passed-in size, and which will not split regions
+    /// beyond the passed-in depth.
+    pub fn with_max_depth(region: R, size: u8, max_depth: u8) -> ObjectTree<R, P, T> {
+        ObjectTree {
+            region,
+            objects: vec![],
+            kind: Bucket { bucket_limit: size, splits_left: max_depth },
+            point: PhantomData
+        }
+    }
+
+    /// Insert an object into the tree, returns true if the object
+    /// overlaps the tree's region and was inserted and false if not.
+    pub fn insert(&mut self, object: T) -> bool {
+        let bounds = object.bounds();
+        if !self.region.overlaps(&bounds) { return false }
+        self.insert_bounded(object, &bounds);
+        true
+    }
+
+    fn insert_bounded(&mut self, object: T, bounds: &R) {
+        match self.kind {
+            Branch { ref mut subregions } => {
+                if let Some(sub_node) = subregions.iter_mut().find(|sub_node| sub_node.region.contains_region(bounds)) {
+                    return sub_node.insert_bounded(object, bounds)
+                }
+            },
+            Bucket { bucket_limit, splits_left } => {
+                if self.objects.len() >= bucket_limit as usize && splits_left > 0 {
+                    self.split();
+                    return self.insert_bounded(object, bounds)
+                }
+            }
+        }
+
+        // Either a bucket with room, or an object straddling sub-regions.
+        self.objects.push(object);
+    }
+
+    // Turn a bucket into a branch, pushing every object which fits
+    // into one of the new sub-regions down into it.
+    fn split(&mut self) {
+        let (bucket_limit, splits_left) = match self.kind {
+            Bucket { bucket_limit, splits_left } => (bucket_limit, splits_left),
+            Branch { .. } => unreachable!()
+        };
+
+        let mut subregions: Vec<ObjectTree<R, P, T>> = self.region
+            .split()
+            .into_iter()
+            .map(|r| ObjectTree::with_max_depth(r, bucket_limit, splits_left - 1))
+            .collect();
+
+        let mut straddling = vec![];
+        for object in self.objects.drain(..) {
+            let bounds = object.bounds();
+            match subregions.iter_mut().find(|sub_node| sub_node.region.contains_region(&bounds)) {
+                Some(sub_node) => sub_node.objects.push(object),
+                None => straddling.push(object)
+            }
+        }
+
+        for sub_node in &mut subregions {
+            if sub_node.objects.len() > bucket_limit as usize && splits_left > 1 {
+                sub_node.split();
+            }
+        }
+
+        self.objects = straddling;
+        self.kind = Branch { subregions };
+    }
+
+    /// Remove an object from the tree, returns true if the object was
+    /// found and removed and false if not.
+    ///
+    /// Any branch left holding no more objects than fit in a single
+    /// bucket is collapsed back into a bucket.
+    pub fn remove(&mut self, object: &T) -> bool where T: PartialEq {
+        let bounds = object.bounds();
+        self.region.overlaps(&bounds) && self.remove_bounded(object, &bounds)
+    }
+
+    fn remove_bounded(&mut self, object: &T, bounds: &R) -> bool where T: PartialEq {
+        let removed = match self.objects.iter().position(|x| x == object) {
+            Some(idx) => {
+                self.objects.swap_remove(idx);
+                true
+            },
+            None => match self.kind {
+                Branch { ref mut subregions } => {
+                    subregions
+                        .iter_mut()
+                        .find(|sub_node| sub_node.region.contains_region(bounds))
+                        .map_or(false, |sub_node| sub_node.remove_bounded(object, bounds))
+                },
+                Bucket { .. } => false
+            }
+        };
+
+        if removed {
+            self.merge();
+        }
+        removed
+    }
+
+    // Collapse a branch of buckets back into a bucket, if all their
+    // objects fit in one.
+    fn merge(&mut self) {
+        let (bucket_limit, splits_left) = match self.kind {
+            Branch { ref subregions } => {
+                let mut total = self.objects.len();
+                let mut limits = (0, 0);
+                for sub_node in subregions {
+                    match sub_node.kind {
+                        Bucket { bucket_limit, splits_left } => {
+                            total += sub_node.objects.len();
+                            limits = (bucket_limit, splits_left + 1);
+                        },
+                        Branch { .. } => return
+                    }
+                }
+
+                if total > limits.0 as usize { return }
+                limits
+            },
+            Bucket { .. } => return
+        };
+
+        if let Branch { subregions } = mem::replace(&mut self.kind, Bucket { bucket_limit, splits_left }) {
+            for sub_node in subregions {
+                self.objects.extend(sub_node.objects);
+            }
+        }
+    }
+
+    /// Get all the objects whose bounds overlap the queried region.
+    pub fn range_query<'t, 'q>(&'t self, query: &'q R) -> ObjectQuery<'t, 'q, R, P, T> {
+        ObjectQuery::new(self, Probe::Overlapping(query))
+    }
+
+    /// Get all the objects whose bounds contain the point.
+    pub fn stab<'t, 'q>(&'t self, point: &'q P) -> ObjectQuery<'t, 'q, R, P, T> {
+        ObjectQuery::new(self, Probe::Containing(point))
+    }
+
+    /// The number of objects in the tree.
+    pub fn len(&self) -> usize {
+        self.objects.len() + match self.kind {
+            Bucket { .. } => 0,
+            Branch { ref subregions } => subregions.iter().map(|sub_node| sub_node.len()).sum()
+        }
+    }
+
+    /// Does the tree hold no objects?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// What an object query is looking for.
+enum Probe<'q, R: 'q, P: 'q> {
+    Overlapping(&'q R),
+    Containing(&'q P)
+}
+
+impl<'q, R: Region<P>, P> Probe<'q, R, P> {
+    fn matches(&self, region: &R) -> bool {
+        match *self {
+            Probe::Overlapping(query) => region.overlaps(query),
+            Probe::Containing(point) => region.contains(point)
+        }
+    }
+}
+
+/// An iterator over the objects of an `ObjectTree` which overlap a
+/// region, or contain a point.
+//
+// As with `RangeQuery`, this keeps a stack of iterators over the
+// children of the nodes being examined. Branches hold objects too, so
+// each matching node has its own objects checked before its children.
+pub struct ObjectQuery<'t, 'q, R: 't + 'q, P: 't + 'q, T: 't> {
+    probe: Probe<'q, R, P>,
+    objects: slice::Iter<'t, T>,
+    stack: Vec<slice::Iter<'t, ObjectTree<R, P, T>>>
+}
+
+impl<'t, 'q, R: Region<P>, P, T: Bounded<R>> ObjectQuery<'t, 'q, R, P, T> {
+    // The root's objects are always examined, since they may reach
+    // outside of its region.
+    fn new(tree: &'t ObjectTree<R, P, T>, probe: Probe<'q, R, P>) -> ObjectQuery<'t, 'q, R, P, T> {
+        let stack = match tree.kind {
+            Branch { ref subregions } => vec![subregions.iter()],
+            Bucket { .. } => vec![]
+        };
+
+        ObjectQuery { probe, objects: tree.objects.iter(), stack }
+    }
+}
+
+impl<'t, 'q, R: Region<P>, P, T: Bounded<R>> Iterator for ObjectQuery<'t, 'q, R, P, T> {
+    type Item = &'t T;
+
+    fn next(&mut self) -> Option<&'t T> {
+        loop {
+            for object in &mut self.objects {
+                if self.probe.matches(&object.bounds()) {
+                    return Some(object)
+                }
+            }
+
+            // find the next matching node, dropping exhausted levels.
+            let node = loop {
+                match self.stack.last_mut()?.next() {
+                    Some(node) => if self.probe.matches(&node.region) { break node },
+                    None => { self.stack.pop(); }
+                }
+            };
+
+            self.objects = node.objects.iter();
+            if let Branch { ref subregions } = node.kind {
+                self.stack.push(subregions.iter());
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use rayon::prelude::*;
+
+use {NTree, Query, Region, SplitPolicy, Summary};
+use NTreeVariant::{Branch, Bucket};
+
+impl<P, R, S, A> NTree<R, P, S, A>
+where P: PartialEq + Sync, R: Region<P> + Sync, S: SplitPolicy<R> + Sync, A: Summary<P> + Sync {
+    /// Get all the points within the queried region, as `range_query`
+    /// does, in parallel.
+    ///
+    /// Work is divided between threads by sub-region, so the points
+    /// come out in no particular order. As with `range_query`, the
+    /// query can be any shape implementing `Query`.
+    pub fn par_range_query<'t, 'q, Q>(&'t self, query: &'q Q) -> ParRangeQuery<'t, 'q, R, P, S, A, Q>
+    where Q: Query<R, P> + Sync + ?Sized {
+        ParRangeQuery { producer: NodeProducer::new(self, Some(query)) }
+    }
+
+    /// Iterate over all the points in the n-tree in parallel.
+    pub fn par_iter<'t>(&'t self) -> ParIter<'t, R, P, S, A> {
+        ParIter { producer: NodeProducer::new(self, None) }
+    }
+
+    /// Run many range queries at once, spread across threads, returning
+    /// the points found by each query in the same order as the queries.
+    pub fn par_range_queries<'t, Q: Query<R, P> + Sync>(&'t self, queries: &[Q]) -> Vec<Vec<&'t P>> {
+        queries
+            .par_iter()
+            .map(|query| self.range_query(query).collect())
+            .collect()
+    }
+}
+
+/// A parallel iterator over the points within a region.
+pub struct ParRangeQuery<'t, 'q, R: 't, P: 't + PartialEq, S: 't, A: 't, Q: 'q + ?Sized = R> {
+    producer: NodeProducer<'t, 'q, R, P, S, A, Q>
+}
+
+/// A parallel iterator over all the points in an n-tree.
+pub struct ParIter<'t, R: 't, P: 't + PartialEq, S: 't, A: 't> {
+    producer: NodeProducer<'t, 't, R, P, S, A, R>
+}
+
+impl<'t, 'q, R, P, S, A, Q> ParallelIterator for ParRangeQuery<'t, 'q, R, P, S, A, Q>
+where P: PartialEq + Sync, R: Region<P> + Sync, S: SplitPolicy<R> + Sync, A: Summary<P> + Sync, Q: Query<R, P> + Sync + ?Sized {
+    type Item = &'t P;
+
+    fn drive_unindexed<C: UnindexedConsumer<&'t P>>(self, consumer: C) -> C::Result {
+        bridge_unindexed(self.producer, consumer)
+    }
+}
+
+impl<'t, R, P, S, A> ParallelIterator for ParIter<'t, R, P, S, A>
+where P: PartialEq + Sync, R: Region<P> + Sync, S: SplitPolicy<R> + Sync, A: Summary<P> + Sync {
+    type Item = &'t P;
+
+    fn drive_unindexed<C: UnindexedConsumer<&'t P>>(self, consumer: C) -> C::Result {
+        bridge_unindexed(self.producer, consumer)
+    }
+}
+
+// The subtrees left to search, which are split in half between threads,
+// expanding a lone branch into its sub-regions when there is only one.
+// Each half is then walked sequentially by `RangeQuery` or `Iter`.
+struct NodeProducer<'t, 'q, R: 't, P: 't + PartialEq, S: 't, A: 't, Q: 'q + ?Sized> {
+    query: Option<&'q Q>,
+    nodes: Vec<&'t NTree<R, P, S, A>>
+}
+
+impl<'t, 'q, R, P, S, A, Q> NodeProducer<'t, 'q, R, P, S, A, Q>
+where P: PartialEq, R: Region<P>, S: SplitPolicy<R>, A: Summary<P>, Q: Query<R, P> + ?Sized {
+    fn new(tree: &'t NTree<R, P, S, A>, query: Option<&'q Q>) -> NodeProducer<'t, 'q, R, P, S, A, Q> {
+        let mut producer = NodeProducer { query, nodes: vec![] };
+        if producer.wanted(tree) {
+            producer.nodes.push(tree);
+        }
+        producer
+    }
+
+    fn wanted(&self, node: &NTree<R, P, S, A>) -> bool {
+        self.query.map_or(true, |query| query.may_overlap(&node.region))
+    }
+}
+
+impl<'t, 'q, R, P, S, A, Q> UnindexedProducer for NodeProducer<'t, 'q, R, P, S, A, Q>
+where P: PartialEq + Sync, R: Region<P> + Sync, S: SplitPolicy<R> + Sync, A: Summary<P> + Sync, Q: Query<R, P> + Sync + ?Sized {
+    type Item = &'t P;
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        while self.nodes.len() == 1 {
+            match self.nodes[0].kind {
+                Branch { ref subregions, .. } => {
+                    self.nodes = subregions.iter().filter(|sub_node| self.wanted(sub_node)).collect();
+                },
+                Bucket { .. } => break
+            }
+        }
+
+        if self.nodes.len() < 2 { return (self, None) }
+        let other = self.nodes.split_off(self.nodes.len() / 2);
+        let query = self.query;
+        (self, Some(NodeProducer { query, nodes: other }))
+    }
+
+    fn fold_with<F: Folder<&'t P>>(self, mut folder: F) -> F {
+        for node in self.nodes {
+            folder = match self.query {
+                Some(query) => folder.consume_iter(node.range_query(query)),
+                None => folder.consume_iter(node.iter())
+            };
+            if folder.full() { break }
+        }
+        folder
+    }
+}
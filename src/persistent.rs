@@ -0,0 +1,316 @@
+//! An immutable n-tree whose updates share structure with the original.
+
+use std::slice;
+use std::sync::Arc;
+
+use {BucketLimit, Query, Region, SplitPolicy, DEFAULT_MAX_DEPTH};
+use self::Kind::{Branch, Bucket};
+
+/// A persistent n-tree: `insert` and `remove` leave the tree alone and
+/// return an updated copy instead.
+///
+/// Only the nodes on the path to the changed bucket are copied; every
+/// other subtree is shared between the old and new trees through an
+/// `Arc`. Cloning the tree is cheap, so readers can hold on to a
+/// consistent snapshot while a writer keeps producing new versions.
+pub struct PersistentNTree<R, P, S = BucketLimit> {
+    root: Arc<Node<R, P, S>>
+}
+
+struct Node<R, P, S> {
+    region: R,
+    kind: Kind<R, P, S>
+}
+
+enum Kind<R, P, S> {
+    /// A leaf of the tree, which contains points.
+    Bucket {
+        points: Vec<P>,
+        policy: S,
+        depth: usize
+    },
+    /// An interior node of the tree, which contains n subtrees.
+    Branch {
+        subregions: Vec<Arc<Node<R, P, S>>>,
+        // The number of points in all the subtrees, as in `NTree`.
+        len: usize
+    }
+}
+
+impl<R, P, S> Clone for PersistentNTree<R, P, S> {
+    fn clone(&self) -> PersistentNTree<R, P, S> {
+        PersistentNTree { root: self.root.clone() }
+    }
+}
+
+impl<R: Region<P>, P: PartialEq + Clone> PersistentNTree<R, P> {
+    /// Create a new, empty persistent n-tree over the region, whose
+    /// buckets are limited to the passed-in size.
+    ///
+    /// The tree is limited to a depth of `DEFAULT_MAX_DEPTH`.
+    pub fn new(region: R, size: u8) -> PersistentNTree<R, P> {
+        PersistentNTree::with_policy(region, BucketLimit {
+            bucket_limit: size as usize,
+            max_depth: DEFAULT_MAX_DEPTH as usize
+        })
+    }
+}
+
+impl<R: Region<P>, P: PartialEq + Clone, S: SplitPolicy<R>> PersistentNTree<R, P, S> {
+    /// Create a new, empty persistent n-tree over the region, whose
+    /// buckets split and merge as the policy decides.
+    pub fn with_policy(region: R, policy: S) -> PersistentNTree<R, P, S> {
+        PersistentNTree { root: Arc::new(Node::branch(region, &policy, 0, vec![])) }
+    }
+
+    /// Get a copy of the n-tree with the point inserted, or `None` if
+    /// the point is outside of the n-tree.
+    pub fn insert(&self, point: P) -> Option<PersistentNTree<R, P, S>> {
+        if !self.root.region.contains(&point) { return None }
+        Some(PersistentNTree { root: Arc::new(self.root.inserted(point)) })
+    }
+
+    /// Get a copy of the n-tree with the point removed, or `None` if
+    /// the point is not in the n-tree.
+    ///
+    /// As with `NTree::remove`, branches the policy allows to merge are
+    /// collapsed back into buckets.
+    pub fn remove(&self, point: &P) -> Option<PersistentNTree<R, P, S>> {
+        if !self.root.region.contains(point) { return None }
+        self.root.removed(point).map(|root| PersistentNTree { root: Arc::new(root) })
+    }
+
+    /// Get all the points within the queried region, as
+    /// `NTree::range_query` does.
+    ///
+    /// The query can be any shape implementing `Query`.
+    pub fn range_query<'t, 'q, Q>(&'t self, query: &'q Q) -> RangeQuery<'t, 'q, R, P, S, Q>
+    where Q: Query<R, P> + ?Sized {
+        RangeQuery {
+            query,
+            points: [].iter(),
+            stack: vec![slice::from_ref(&self.root).iter()]
+        }
+    }
+
+    /// Iterate over all the points in the n-tree.
+    pub fn iter<'t>(&'t self) -> Iter<'t, R, P, S> {
+        Iter { points: [].iter(), stack: vec![slice::from_ref(&self.root).iter()] }
+    }
+
+    /// The number of points in the n-tree.
+    ///
+    /// As with `NTree`, each branch keeps count of the points below it,
+    /// so this takes constant time.
+    pub fn len(&self) -> usize {
+        self.root.len()
+    }
+
+    /// Does the n-tree hold no points?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Is the point contained in the n-tree?
+    pub fn contains(&self, point: &P) -> bool {
+        self.root.region.contains(point)
+    }
+
+    /// Get all the points in the bucket containing a specified point.
+    pub fn nearby(&self, point: &P) -> Option<&[P]> {
+        if !self.root.region.contains(point) { return None }
+
+        let mut current_node = &*self.root;
+        while let Branch { ref subregions, .. } = current_node.kind {
+            current_node = subregions
+                .iter()
+                .find(|sub_node| sub_node.region.contains(point))
+                .unwrap(); //does always exist, due to invariant of R.split()
+        }
+
+        match current_node.kind {
+            Bucket { ref points, .. } => Some(points.as_slice()),
+            Branch { .. } => unreachable!()
+        }
+    }
+}
+
+impl<R, P, S> Node<R, P, S> {
+    fn len(&self) -> usize {
+        match self.kind {
+            Bucket { ref points, .. } => points.len(),
+            Branch { len, .. } => len
+        }
+    }
+}
+
+impl<R: Region<P>, P: PartialEq + Clone, S: SplitPolicy<R>> Node<R, P, S> {
+    // A branch at the given depth, with the points partitioned among
+    // its sub-regions, which split further as the policy decides.
+    fn branch(region: R, policy: &S, depth: usize, points: Vec<P>) -> Node<R, P, S> {
+        let len = points.len();
+        let subregions = region.split();
+        let mut partitions: Vec<Vec<P>> = subregions.iter().map(|_| vec![]).collect();
+        for point in points {
+            let idx = subregions
+                .iter()
+                .position(|sub_region| sub_region.contains(&point))
+                .unwrap(); //does always exist, due to invariant of R.split()
+            partitions[idx].push(point);
+        }
+
+        let subregions = subregions
+            .into_iter()
+            .zip(partitions)
+            .map(|(sub_region, points)| Arc::new(Node::bucket(sub_region, policy, depth + 1, points)))
+            .collect();
+        Node { region, kind: Branch { subregions, len } }
+    }
+
+    // A bucket of the points, or a branch if the policy says to split.
+    fn bucket(region: R, policy: &S, depth: usize, points: Vec<P>) -> Node<R, P, S> {
+        if policy.should_split(&region, depth, points.len()) {
+            Node::branch(region, policy, depth, points)
+        } else {
+            Node { region, kind: Bucket { points, policy: policy.clone(), depth } }
+        }
+    }
+
+    // A copy of this node with the point inserted, sharing every
+    // sub-region the point doesn't land in.
+    fn inserted(&self, point: P) -> Node<R, P, S> {
+        match self.kind {
+            Bucket { ref points, ref policy, depth } => {
+                let mut points = points.clone();
+                points.push(point);
+                Node::bucket(self.region.clone(), policy, depth, points)
+            },
+            Branch { ref subregions, len } => {
+                let idx = subregions
+                    .iter()
+                    .position(|sub_node| sub_node.region.contains(&point))
+                    .unwrap(); //does always exist, due to invariant of R.split()
+
+                let mut subregions = subregions.clone();
+                subregions[idx] = Arc::new(subregions[idx].inserted(point));
+                Node { region: self.region.clone(), kind: Branch { subregions, len: len + 1 } }
+            }
+        }
+    }
+
+    // A copy of this node with the point removed, merging it into a
+    // bucket if the policy allows, or None if the point isn't here.
+    fn removed(&self, point: &P) -> Option<Node<R, P, S>> {
+        match self.kind {
+            Bucket { ref points, ref policy, depth } => {
+                let idx = points.iter().position(|x| x == point)?;
+                let mut points = points.clone();
+                points.swap_remove(idx);
+                Some(Node { region: self.region.clone(), kind: Bucket { points, policy: policy.clone(), depth } })
+            },
+            Branch { ref subregions, .. } => {
+                let idx = subregions
+                    .iter()
+                    .position(|sub_node| sub_node.region.contains(point))
+                    .unwrap(); //does always exist, due to invariant of R.split()
+
+                let mut subregions = subregions.clone();
+                subregions[idx] = Arc::new(subregions[idx].removed(point)?);
+                Some(Node::merged(self.region.clone(), subregions))
+            }
+        }
+    }
+
+    // A branch of the sub-regions, or a single bucket of their points
+    // if they are all buckets and the policy says to merge them.
+    fn merged(region: R, subregions: Vec<Arc<Node<R, P, S>>>) -> Node<R, P, S> {
+        let total = subregions.iter().map(|sub_node| sub_node.len()).sum();
+        for sub_node in &subregions {
+            if let Branch { .. } = sub_node.kind {
+                return Node { region, kind: Branch { subregions, len: total } }
+            }
+        }
+
+        let (policy, depth) = match subregions[0].kind {
+            Bucket { ref policy, depth, .. } => (policy.clone(), depth - 1),
+            Branch { .. } => unreachable!()
+        };
+        if !policy.should_merge(&region, depth, total) {
+            return Node { region, kind: Branch { subregions, len: total } }
+        }
+
+        let mut points = Vec::with_capacity(total);
+        for sub_node in &subregions {
+            if let Bucket { points: ref sub_points, .. } = sub_node.kind {
+                points.extend(sub_points.iter().cloned());
+            }
+        }
+        Node { region, kind: Bucket { points, policy, depth } }
+    }
+}
+
+/// An iterator over the points of a `PersistentNTree` within a region.
+//
+// This is `::RangeQuery`, over shared rather than owned children.
+pub struct RangeQuery<'t, 'q, R: 't, P: 't, S: 't = BucketLimit, Q: 'q + ?Sized = R> {
+    query: &'q Q,
+    points: slice::Iter<'t, P>,
+    stack: Vec<slice::Iter<'t, Arc<Node<R, P, S>>>>
+}
+
+impl<'t, 'q, R, P, S, Q: Query<R, P> + ?Sized> Iterator for RangeQuery<'t, 'q, R, P, S, Q> {
+    type Item = &'t P;
+
+    fn next(&mut self) -> Option<&'t P> {
+        loop {
+            for p in &mut self.points {
+                if self.query.matches(p) {
+                    return Some(p)
+                }
+            }
+
+            // find the next overlapping node, dropping exhausted levels.
+            let node = loop {
+                match self.stack.last_mut()?.next() {
+                    Some(node) => if self.query.may_overlap(&node.region) { break node },
+                    None => { self.stack.pop(); }
+                }
+            };
+
+            match node.kind {
+                Bucket { ref points, .. } => self.points = points.iter(),
+                Branch { ref subregions, .. } => self.stack.push(subregions.iter())
+            }
+        }
+    }
+}
+
+/// An iterator over all the points of a `PersistentNTree`.
+pub struct Iter<'t, R: 't, P: 't, S: 't = BucketLimit> {
+    points: slice::Iter<'t, P>,
+    stack: Vec<slice::Iter<'t, Arc<Node<R, P, S>>>>
+}
+
+impl<'t, R, P, S> Iterator for Iter<'t, R, P, S> {
+    type Item = &'t P;
+
+    fn next(&mut self) -> Option<&'t P> {
+        loop {
+            if let Some(p) = self.points.next() {
+                return Some(p)
+            }
+
+            let node = loop {
+                match self.stack.last_mut()?.next() {
+                    Some(node) => break node,
+                    None => { self.stack.pop(); }
+                }
+            };
+
+            match node.kind {
+                Bucket { ref points, .. } => self.points = points.iter(),
+                Branch { ref subregions, .. } => self.stack.push(subregions.iter())
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use DEFAULT_MAX_DEPTH;
+
+/// Decides when the buckets of an n-tree split and when branches
+/// merge back into buckets.
+///
+/// Depths are counted from the root, which has depth zero. The policy
+/// is cloned into every bucket, so it should be cheap to clone.
+pub trait SplitPolicy<R>: Clone {
+    /// Should a bucket over this region, at this depth, split rather
+    /// than hold this many points?
+    fn should_split(&self, region: &R, depth: usize, len: usize) -> bool;
+
+    /// Should a branch over this region, at this depth, whose buckets
+    /// hold this many points between them, be merged into one bucket?
+    ///
+    /// This is only asked of branches whose children are all buckets.
+    fn should_merge(&self, region: &R, depth: usize, len: usize) -> bool;
+}
+
+/// The default split policy: buckets split once they hold more than
+/// `bucket_limit` points, unless they are at `max_depth`, and branches
+/// merge once their points fit in one bucket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BucketLimit {
+    /// The most points a bucket holds before splitting.
+    pub bucket_limit: usize,
+    /// The depth beyond which buckets never split, but grow instead.
+    pub max_depth: usize
+}
+
+impl BucketLimit {
+    /// Limit buckets to the passed-in size, and the tree to a depth of
+    /// `DEFAULT_MAX_DEPTH`.
+    pub fn new(bucket_limit: usize) -> BucketLimit {
+        BucketLimit { bucket_limit, max_depth: DEFAULT_MAX_DEPTH as usize }
+    }
+}
+
+impl<R> SplitPolicy<R> for BucketLimit {
+    fn should_split(&self, _: &R, depth: usize, len: usize) -> bool {
+        len > self.bucket_limit && depth < self.max_depth
+    }
+
+    fn should_merge(&self, _: &R, _: usize, len: usize) -> bool {
+        len <= self.bucket_limit
+    }
+}
@@ -0,0 +1,62 @@
+use Region;
+
+/// A shape to search an n-tree with, such as a circle, a polygon or a
+/// half-plane, which need not be the same type as the tree's regions.
+///
+/// Every region is also a query, so an n-tree can always be searched
+/// with the same type of region it is split on.
+pub trait Query<R, P> {
+    /// Could any of the points within this region match the query?
+    ///
+    /// This may return true for regions which turn out to hold no
+    /// matching points, as it only decides which regions are searched,
+    /// but must never return false for a region which does.
+    fn may_overlap(&self, region: &R) -> bool;
+
+    /// Does this point match the query?
+    fn matches(&self, point: &P) -> bool;
+
+    /// Does every point within this region match the query?
+    ///
+    /// `range_count` and `range_aggregate` take a covered region's cached
+    /// count or summary rather than testing its points one by one. The
+    /// default never reports a region as covered, which is always correct
+    /// but gives up the shortcut.
+    fn covers(&self, region: &R) -> bool {
+        let _ = region;
+        false
+    }
+}
+
+impl<P, R: Region<P>> Query<R, P> for R {
+    fn may_overlap(&self, region: &R) -> bool {
+        region.overlaps(self)
+    }
+
+    fn matches(&self, point: &P) -> bool {
+        self.contains(point)
+    }
+
+    fn covers(&self, region: &R) -> bool {
+        self.contains_region(region)
+    }
+}
+
+// A query as an iterator over a tree holds it: by reference for range
+// queries, or through an adapter in `NTreeMap`, whose queries are over
+// the positions within its entries rather than the entries themselves.
+pub(crate) trait HeldQuery<R, P> {
+    fn may_overlap(&self, region: &R) -> bool;
+
+    fn matches(&self, point: &P) -> bool;
+}
+
+impl<R, P, Q: Query<R, P> + ?Sized> HeldQuery<R, P> for &Q {
+    fn may_overlap(&self, region: &R) -> bool {
+        Query::may_overlap(*self, region)
+    }
+
+    fn matches(&self, point: &P) -> bool {
+        Query::matches(*self, point)
+    }
+}
@@ -0,0 +1,311 @@
+//! Ready-made regions and points for common spatial indexes.
+//!
+//! `QuadTreeRegion` and `Vec2` make an n-tree a quadtree, `OctreeRegion`
+//! and `Vec3` make it an octree, and `BoxRegion` and `Point` handle any
+//! fixed number of dimensions. All of them work with the built-in metrics.
+//!
+//! These regions are closed: they contain the points on their edges, so
+//! a point on a split line lies in every sub-region sharing it and
+//! belongs to the first of them, as `Region::split` describes.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use {Bounds, Coordinates, Region};
+
+/// A point in two dimensions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Vec2 {
+    /// The x coordinate.
+    pub x: f64,
+    /// The y coordinate.
+    pub y: f64
+}
+
+/// An axis-aligned rectangle, which splits into four quadrants.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QuadTreeRegion {
+    /// The smallest x coordinate in the region.
+    pub x: f64,
+    /// The smallest y coordinate in the region.
+    pub y: f64,
+    /// The extent of the region along the x axis.
+    pub width: f64,
+    /// The extent of the region along the y axis.
+    pub height: f64
+}
+
+impl QuadTreeRegion {
+    /// A square region with its lowest corner at (x, y).
+    pub fn square(x: f64, y: f64, wh: f64) -> QuadTreeRegion {
+        QuadTreeRegion { x, y, width: wh, height: wh }
+    }
+}
+
+impl Region<Vec2> for QuadTreeRegion {
+    fn contains(&self, p: &Vec2) -> bool {
+        self.x <= p.x && self.y <= p.y && (self.x + self.width) >= p.x && (self.y + self.height) >= p.y
+    }
+
+    fn split(&self) -> Vec<QuadTreeRegion> {
+        let halfwidth = self.width / 2.0;
+        let halfheight = self.height / 2.0;
+        vec![
+            QuadTreeRegion {
+                x: self.x,
+                y: self.y,
+                width: halfwidth,
+                height: halfheight
+            },
+
+            QuadTreeRegion {
+                x: self.x,
+                y: self.y + halfheight,
+                width: halfwidth,
+                height: halfheight
+            },
+
+            QuadTreeRegion {
+                x: self.x + halfwidth,
+                y: self.y,
+                width: halfwidth,
+                height: halfheight
+            },
+
+            QuadTreeRegion {
+                x: self.x + halfwidth,
+                y: self.y + halfheight,
+                width: halfwidth,
+                height: halfheight
+            }
+        ]
+    }
+
+    fn overlaps(&self, other: &QuadTreeRegion) -> bool {
+        self.x <= other.x + other.width && other.x <= self.x + self.width
+            && self.y <= other.y + other.height && other.y <= self.y + self.height
+    }
+
+    fn contains_region(&self, other: &QuadTreeRegion) -> bool {
+        self.x <= other.x && other.x + other.width <= self.x + self.width
+            && self.y <= other.y && other.y + other.height <= self.y + self.height
+    }
+}
+
+impl Coordinates for Vec2 {
+    fn dimensions(&self) -> usize { 2 }
+
+    fn coordinate(&self, axis: usize) -> f64 {
+        if axis == 0 { self.x } else { self.y }
+    }
+}
+
+impl Bounds for QuadTreeRegion {
+    fn lower(&self, axis: usize) -> f64 {
+        if axis == 0 { self.x } else { self.y }
+    }
+
+    fn upper(&self, axis: usize) -> f64 {
+        if axis == 0 { self.x + self.width } else { self.y + self.height }
+    }
+}
+
+/// A point in three dimensions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Vec3 {
+    /// The x coordinate.
+    pub x: f64,
+    /// The y coordinate.
+    pub y: f64,
+    /// The z coordinate.
+    pub z: f64
+}
+
+/// An axis-aligned box, which splits into eight octants.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OctreeRegion {
+    /// The smallest x coordinate in the region.
+    pub x: f64,
+    /// The smallest y coordinate in the region.
+    pub y: f64,
+    /// The smallest z coordinate in the region.
+    pub z: f64,
+    /// The extent of the region along the x axis.
+    pub width: f64,
+    /// The extent of the region along the y axis.
+    pub height: f64,
+    /// The extent of the region along the z axis.
+    pub depth: f64
+}
+
+impl OctreeRegion {
+    /// A cube region with its lowest corner at (x, y, z).
+    pub fn cube(x: f64, y: f64, z: f64, whd: f64) -> OctreeRegion {
+        OctreeRegion { x, y, z, width: whd, height: whd, depth: whd }
+    }
+}
+
+impl Region<Vec3> for OctreeRegion {
+    fn contains(&self, p: &Vec3) -> bool {
+        self.x <= p.x && p.x <= self.x + self.width
+            && self.y <= p.y && p.y <= self.y + self.height
+            && self.z <= p.z && p.z <= self.z + self.depth
+    }
+
+    fn split(&self) -> Vec<OctreeRegion> {
+        let halfwidth = self.width / 2.0;
+        let halfheight = self.height / 2.0;
+        let halfdepth = self.depth / 2.0;
+
+        let mut octants = Vec::with_capacity(8);
+        for &x in &[self.x, self.x + halfwidth] {
+            for &y in &[self.y, self.y + halfheight] {
+                for &z in &[self.z, self.z + halfdepth] {
+                    octants.push(OctreeRegion {
+                        x, y, z,
+                        width: halfwidth,
+                        height: halfheight,
+                        depth: halfdepth
+                    });
+                }
+            }
+        }
+        octants
+    }
+
+    fn overlaps(&self, other: &OctreeRegion) -> bool {
+        self.x <= other.x + other.width && other.x <= self.x + self.width
+            && self.y <= other.y + other.height && other.y <= self.y + self.height
+            && self.z <= other.z + other.depth && other.z <= self.z + self.depth
+    }
+
+    fn contains_region(&self, other: &OctreeRegion) -> bool {
+        self.x <= other.x && other.x + other.width <= self.x + self.width
+            && self.y <= other.y && other.y + other.height <= self.y + self.height
+            && self.z <= other.z && other.z + other.depth <= self.z + self.depth
+    }
+}
+
+impl Coordinates for Vec3 {
+    fn dimensions(&self) -> usize { 3 }
+
+    fn coordinate(&self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z
+        }
+    }
+}
+
+impl Bounds for OctreeRegion {
+    fn lower(&self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z
+        }
+    }
+
+    fn upper(&self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x + self.width,
+            1 => self.y + self.height,
+            _ => self.z + self.depth
+        }
+    }
+}
+
+/// A point in N dimensions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Point<const N: usize>(
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::array"))]
+    pub [f64; N]
+);
+
+/// An axis-aligned box in N dimensions, which splits into 2^N
+/// sub-boxes by halving every axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BoxRegion<const N: usize> {
+    /// The smallest coordinate in the region along each axis.
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::array"))]
+    pub min: [f64; N],
+    /// The largest coordinate in the region along each axis.
+    #[cfg_attr(feature = "serde", serde(with = "::serialize::array"))]
+    pub max: [f64; N]
+}
+
+impl<const N: usize> BoxRegion<N> {
+    /// A box spanning from the min corner to the max corner.
+    pub fn new(min: [f64; N], max: [f64; N]) -> BoxRegion<N> {
+        BoxRegion { min, max }
+    }
+
+    /// A box with the same extent along every axis, with its lowest
+    /// corner at min.
+    pub fn cube(min: [f64; N], size: f64) -> BoxRegion<N> {
+        let mut max = min;
+        for x in &mut max { *x += size; }
+        BoxRegion { min, max }
+    }
+}
+
+impl<const N: usize> Region<Point<N>> for BoxRegion<N> {
+    fn contains(&self, p: &Point<N>) -> bool {
+        (0..N).all(|axis| self.min[axis] <= p.0[axis] && p.0[axis] <= self.max[axis])
+    }
+
+    fn split(&self) -> Vec<BoxRegion<N>> {
+        let mut mid = self.min;
+        for (m, &max) in mid.iter_mut().zip(&self.max) {
+            *m += (max - *m) / 2.0;
+        }
+
+        // Bit `axis` of each index picks the upper or lower half on that axis.
+        (0..1usize << N)
+            .map(|index| {
+                let mut sub = *self;
+                for (axis, &m) in mid.iter().enumerate() {
+                    if index & (1 << axis) == 0 {
+                        sub.max[axis] = m;
+                    } else {
+                        sub.min[axis] = m;
+                    }
+                }
+                sub
+            })
+            .collect()
+    }
+
+    fn overlaps(&self, other: &BoxRegion<N>) -> bool {
+        (0..N).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+
+    fn contains_region(&self, other: &BoxRegion<N>) -> bool {
+        (0..N).all(|axis| self.min[axis] <= other.min[axis] && other.max[axis] <= self.max[axis])
+    }
+}
+
+impl<const N: usize> Coordinates for Point<N> {
+    fn dimensions(&self) -> usize { N }
+
+    fn coordinate(&self, axis: usize) -> f64 {
+        self.0[axis]
+    }
+}
+
+impl<const N: usize> Bounds for BoxRegion<N> {
+    fn lower(&self, axis: usize) -> f64 {
+        self.min[axis]
+    }
+
+    fn upper(&self, axis: usize) -> f64 {
+        self.max[axis]
+    }
+}
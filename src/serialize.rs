@@ -0,0 +1,205 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde::de::{self, Deserializer, Error, SeqAccess, Visitor};
+use serde::ser::{SerializeStruct, Serializer};
+
+use {NTree, Region, Summary};
+use NTreeVariant::{Branch, Bucket};
+
+// An n-tree is written as its root region and a flat list of its nodes,
+// numbered breadth-first so the children of every branch are contiguous,
+// as in the files `write_to` writes. Nesting the nodes instead would run
+// into the recursion limit of formats like JSON in the deepest trees.
+//
+// Only the root's region is written. The regions of the other nodes are
+// split from their parents' again on load, so they can't disagree. The
+// split policy is likewise written once, and every bucket is given a
+// copy of it on load.
+#[derive(Serialize)]
+#[serde(rename = "Node")]
+enum NodeRef<'t, P: 't> {
+    Bucket { points: &'t [P], depth: usize },
+    Branch { children: usize }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+enum Node<P> {
+    Bucket { points: Vec<P>, depth: usize },
+    Branch { children: usize }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "NTree")]
+struct Tree<R, P, S> {
+    region: R,
+    policy: S,
+    nodes: Vec<Node<P>>
+}
+
+impl<R, P, S, A> Serialize for NTree<R, P, S, A>
+where R: Serialize, P: PartialEq + Serialize, S: Serialize {
+    fn serialize<T: Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+        // Number the nodes breadth-first.
+        let mut order = vec![self];
+        let mut idx = 0;
+        while idx < order.len() {
+            if let Branch { ref subregions, .. } = order[idx].kind {
+                order.extend(subregions);
+            }
+            idx += 1;
+        }
+
+        let nodes: Vec<NodeRef<P>> = order
+            .iter()
+            .map(|node| match node.kind {
+                Bucket { ref points, depth, .. } => NodeRef::Bucket { points, depth },
+                Branch { ref subregions, .. } => NodeRef::Branch { children: subregions.len() }
+            })
+            .collect();
+
+        // Every bucket shares the tree's policy, and there is always one.
+        let policy = order
+            .iter()
+            .find_map(|node| match node.kind {
+                Bucket { ref policy, .. } => Some(policy),
+                Branch { .. } => None
+            })
+            .unwrap();
+
+        let mut tree = serializer.serialize_struct("NTree", 3)?;
+        tree.serialize_field("region", &self.region)?;
+        tree.serialize_field("policy", policy)?;
+        tree.serialize_field("nodes", &nodes)?;
+        tree.end()
+    }
+}
+
+// The nodes are checked on the way down, splitting the regions of the
+// branches and making sure every point is in the bucket routing would
+// find it in. The tree is then put together on the way back up, which
+// is when summaries and the counts of branches are rebuilt.
+impl<'de, R, P, S, A> Deserialize<'de> for NTree<R, P, S, A>
+where R: Region<P> + Deserialize<'de>, P: PartialEq + Deserialize<'de>, S: Clone + Deserialize<'de>, A: Summary<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<NTree<R, P, S, A>, D::Error> {
+        let Tree { region, policy, nodes }: Tree<R, P, S> = Tree::deserialize(deserializer)?;
+        if nodes.is_empty() {
+            return Err(D::Error::custom("an n-tree has no nodes"))
+        }
+
+        let mut regions = vec![region];
+        let mut children = vec![0..0; nodes.len()];
+        let mut levels = vec![0; nodes.len()];
+        for (idx, node) in nodes.iter().enumerate() {
+            if idx >= regions.len() {
+                return Err(D::Error::custom("a node is not the child of any branch"))
+            }
+
+            if let Node::Branch { children: count } = *node {
+                let split = regions[idx].split();
+                if count == 0 || count != split.len() {
+                    return Err(D::Error::custom("a branch doesn't have one child for each of its sub-regions"))
+                }
+
+                children[idx] = regions.len()..regions.len() + count;
+                for child in children[idx].clone().filter(|&child| child < nodes.len()) {
+                    levels[child] = levels[idx] + 1;
+                }
+                regions.extend(split);
+            }
+        }
+        if regions.len() != nodes.len() {
+            return Err(D::Error::custom("a branch has more children than there are nodes"))
+        }
+
+        // The bucket routing from the root takes a point to, if any.
+        let route = |point: &P| {
+            let mut idx = 0;
+            while !children[idx].is_empty() {
+                idx = children[idx].clone().find(|&child| regions[child].contains(point))?;
+            }
+            Some(idx)
+        };
+
+        for (idx, node) in nodes.iter().enumerate() {
+            if let Node::Bucket { ref points, depth, .. } = *node {
+                if depth != levels[idx] {
+                    return Err(D::Error::custom("a bucket's depth doesn't match its place in the tree"))
+                }
+                if !points.iter().all(|point| regions[idx].contains(point)) {
+                    return Err(D::Error::custom("a point lies outside of its bucket's region"))
+                }
+                if !points.iter().all(|point| route(point) == Some(idx)) {
+                    return Err(D::Error::custom("a point lies in a bucket it isn't routed to"))
+                }
+            }
+        }
+
+        let mut built: Vec<Option<NTree<R, P, S, A>>> = nodes.iter().map(|_| None).collect();
+        for (idx, (node, region)) in nodes.into_iter().zip(regions).enumerate().rev() {
+            let kind = match node {
+                Node::Bucket { points, depth } => Bucket { points, policy: policy.clone(), depth },
+                Node::Branch { .. } => {
+                    let subregions: Vec<_> = children[idx]
+                        .clone()
+                        .map(|child| built[child].take().unwrap())
+                        .collect();
+                    Branch { len: subregions.iter().map(self::len).sum(), subregions }
+                }
+            };
+
+            let mut tree = NTree { region, kind, summary: A::empty() };
+            tree.resummarize();
+            built[idx] = Some(tree);
+        }
+
+        // the root is node zero.
+        Ok(built[0].take().unwrap())
+    }
+}
+
+// The number of points in a node, whose own count is already known.
+fn len<R, P: PartialEq, S, A>(node: &NTree<R, P, S, A>) -> usize {
+    match node.kind {
+        Bucket { ref points, .. } => points.len(),
+        Branch { len, .. } => len
+    }
+}
+
+// Serde only implements its traits for arrays of up to 32 elements, so
+// the coordinates of `Point` and `BoxRegion` are written as tuples by hand.
+pub mod array {
+    use super::*;
+    use serde::ser::{SerializeTuple, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(array: &[f64; N], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for x in array {
+            tuple.serialize_element(x)?;
+        }
+        tuple.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[f64; N], D::Error> {
+        deserializer.deserialize_tuple(N, ArrayVisitor)
+    }
+
+    struct ArrayVisitor<const N: usize>;
+
+    impl<'de, const N: usize> Visitor<'de> for ArrayVisitor<N> {
+        type Value = [f64; N];
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an array of {} numbers", N)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<[f64; N], A::Error> {
+            let mut array = [0.0; N];
+            for (i, x) in array.iter_mut().enumerate() {
+                *x = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(i, &self))?;
+            }
+            Ok(array)
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use {NTree, Region};
+use NTreeVariant::{Branch, Bucket};
+
+/// Statistics about the shape of an n-tree, for tuning bucket limits.
+///
+/// Depths are counted from the root, which has depth zero.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// The number of points in the tree.
+    pub points: usize,
+    /// The number of nodes in the tree, both branches and buckets.
+    pub nodes: usize,
+    /// The number of buckets (leaves) in the tree.
+    pub leaves: usize,
+    /// The depth of the deepest bucket.
+    pub max_depth: usize,
+    /// The mean depth of the buckets.
+    pub avg_depth: f64,
+    /// How full the buckets are: `bucket_fill[n]` is the number of
+    /// buckets holding exactly `n` points.
+    pub bucket_fill: Vec<usize>
+}
+
+impl Stats {
+    pub(crate) fn of<R: Region<P>, P: PartialEq, S, A>(tree: &NTree<R, P, S, A>) -> Stats {
+        let mut stats = Stats::default();
+        let mut depth_sum = 0;
+        stats.visit(tree, 0, &mut depth_sum);
+
+        if stats.leaves > 0 {
+            stats.avg_depth = depth_sum as f64 / stats.leaves as f64;
+        }
+        stats
+    }
+
+    fn visit<R, P: PartialEq, S, A>(&mut self, node: &NTree<R, P, S, A>, depth: usize, depth_sum: &mut usize) {
+        self.nodes += 1;
+        match node.kind {
+            Bucket { ref points, .. } => {
+                self.points += points.len();
+                self.leaves += 1;
+                self.max_depth = self.max_depth.max(depth);
+                *depth_sum += depth;
+
+                if self.bucket_fill.len() <= points.len() {
+                    self.bucket_fill.resize(points.len() + 1, 0);
+                }
+                self.bucket_fill[points.len()] += 1;
+            },
+            Branch { ref subregions, .. } => {
+                for sub_node in subregions {
+                    self.visit(sub_node, depth + 1, depth_sum);
+                }
+            }
+        }
+    }
+}
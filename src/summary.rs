@@ -0,0 +1,35 @@
+/// A summary of a set of points, such as their total mass, bounding box
+/// or the sum and count needed for an average, which every node of an
+/// n-tree keeps for the points beneath it.
+///
+/// Summaries form a monoid: `combine` must be associative, and
+/// combining with `empty()` must leave a summary unchanged. Points are
+/// kept in no particular order, so `combine` must be commutative too.
+///
+/// The unit type summarizes nothing, and is the summary of n-trees
+/// which don't need one.
+pub trait Summary<P>: Clone {
+    /// The summary of no points at all.
+    fn empty() -> Self;
+
+    /// The summary of a single point.
+    fn of(point: &P) -> Self;
+
+    /// The summary of the points of both summaries together.
+    fn combine(&self, other: &Self) -> Self;
+}
+
+impl<P> Summary<P> for () {
+    fn empty() {}
+    fn of(_: &P) {}
+    fn combine(&self, _: &()) {}
+}
+
+/// Summaries with a center, such as a center of mass, which stand in
+/// for their points when seen from far enough away.
+pub trait Centered<P> {
+    /// The center of the summarized points.
+    ///
+    /// This is only asked of summaries of at least one point.
+    fn center(&self) -> P;
+}
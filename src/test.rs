@@ -10,86 +10,1416 @@ use self::test::Bencher;
 #[cfg(feature = "bench")]
 use self::rand::{random, XorShiftRng, Rng};
 
-use self::fixtures::{QuadTreeRegion, Vec2};
-use {NTree};
+#[cfg(feature = "serde")]
+extern crate serde_json;
+
+use std::borrow::Borrow;
+
+use regions::{QuadTreeRegion, Vec2};
+use {BucketLimit, Chebyshev, Euclidean, Manhattan, Metric, NTree, Region, SplitPolicy};
+
+// Points spread over the square from (0, 0) to (100, 100), each nudged
+// up and to the right by the offset.
+fn scattered(n: usize, offset: f64) -> impl Iterator<Item = Vec2> {
+    (0..n).map(move |i| Vec2 { x: (i * 37 % 100) as f64 + offset, y: (i * 61 % 100) as f64 + offset })
+}
+
+// Points in a fixed order, to compare queries whose order is unspecified.
+fn sorted<I>(points: I) -> Vec<Vec2>
+where I: IntoIterator, I::Item: Borrow<Vec2> {
+    let mut points: Vec<Vec2> = points.into_iter().map(|p| *p.borrow()).collect();
+    points.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap().then(a.y.partial_cmp(&b.y).unwrap()));
+    points
+}
+
+#[test]
+fn test_contains() {
+    let ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    assert!(ntree.contains(&Vec2 { x: 50.0, y: 50.0 }));
+}
+
+#[test]
+fn test_insert() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    assert!(ntree.insert(Vec2 { x: 50.0, y: 50.0 }));
+    assert_eq!(ntree.nearby(&Vec2 { x: 40.0, y: 40.0 }), Some(&[Vec2 { x: 50.0, y: 50.0 }] as &[_]));
+}
+
+
+#[test]
+fn test_remove() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    ntree.insert(Vec2 { x: 50.0, y: 50.0 });
+    assert!(ntree.remove(&Vec2 { x: 50.0, y: 50.0 }));
+    assert_eq!(ntree.nearby(&Vec2 { x: 40.0, y: 40.0 }), Some(&[] as &[_]));
+    assert!(!ntree.remove(&Vec2 { x: 50.0, y: 50.0 }));
+}
+
+#[test]
+fn test_remove_merges_buckets() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    let points: Vec<Vec2> = scattered(100, 0.0).collect();
+    for p in &points { ntree.insert(*p); }
+
+    for p in &points[4..] {
+        assert!(ntree.remove(p));
+    }
+
+    // Every split has been undone, so the remaining points share one bucket.
+    let mut remaining = ntree.nearby(&Vec2 { x: 99.0, y: 99.0 }).unwrap().to_vec();
+    remaining.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+    let mut expected = points[..4].to_vec();
+    expected.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+    assert_eq!(remaining, expected);
+
+    // The tree still splits again as it refills.
+    for p in &points[4..] { ntree.insert(*p); }
+    assert!(ntree.nearby(&Vec2 { x: 99.0, y: 99.0 }).unwrap().len() <= 4);
+    assert_eq!(ntree.range_query(&QuadTreeRegion::square(0.0, 0.0, 100.0)).count(), 100);
+}
+
+#[test]
+fn test_points_on_split_lines() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    let points: Vec<Vec2> = (0..=4)
+        .flat_map(|i| (0..=4).map(move |j| Vec2 { x: i as f64 * 25.0, y: j as f64 * 25.0 }))
+        .collect();
+    for p in &points { assert!(ntree.insert(*p)); }
+
+    // Each point lies in exactly one bucket, the one routing finds.
+    assert_eq!(ntree.range_query(&QuadTreeRegion::square(0.0, 0.0, 100.0)).count(), points.len());
+    for p in &points {
+        assert!(ntree.nearby(p).unwrap().contains(p));
+    }
+
+    for p in &points { assert!(ntree.remove(p)); }
+    assert!(ntree.is_empty());
+}
+
+#[test]
+fn test_insert_identical_points() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    for _ in 0..1000 {
+        assert!(ntree.insert(Vec2 { x: 12.5, y: 12.5 }));
+    }
+
+    assert_eq!(ntree.nearby(&Vec2 { x: 12.5, y: 12.5 }).unwrap().len(), 1000);
+    assert_eq!(ntree.range_query(&QuadTreeRegion::square(0.0, 0.0, 100.0)).count(), 1000);
+
+    for _ in 0..1000 {
+        assert!(ntree.remove(&Vec2 { x: 12.5, y: 12.5 }));
+    }
+    assert_eq!(ntree.nearby(&Vec2 { x: 12.5, y: 12.5 }), Some(&[] as &[_]));
+}
+
+#[test]
+fn test_with_max_depth() {
+    let mut ntree = NTree::with_max_depth(QuadTreeRegion::square(0.0, 0.0, 100.0), 1, 2);
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+    ntree.insert(Vec2 { x: 30.0, y: 30.0 });
+    ntree.insert(Vec2 { x: 40.0, y: 40.0 });
+
+    // The bottom left quadrant has split once, and then overflowed.
+    assert_eq!(ntree.nearby(&Vec2 { x: 10.0, y: 10.0 }), Some(&[Vec2 { x: 10.0, y: 10.0 }] as &[_]));
+    assert_eq!(ntree.nearby(&Vec2 { x: 30.0, y: 30.0 }),
+               Some(&[Vec2 { x: 30.0, y: 30.0 }, Vec2 { x: 40.0, y: 40.0 }] as &[_]));
+}
+
+#[test]
+fn test_bucket_limit_policy() {
+    let region = QuadTreeRegion::square(0.0, 0.0, 100.0);
+    let mut ntree = NTree::with_policy(region, BucketLimit::new(1000));
+    for i in 0..1000 {
+        ntree.insert(Vec2 { x: (i % 50) as f64, y: (i / 50) as f64 });
+    }
+
+    // A limit too large for a u8 still keeps every point in one bucket.
+    assert_eq!(ntree.len(), 1000);
+    assert_eq!(ntree.stats().leaves, 4);
+    assert_eq!(ntree.nearby(&Vec2 { x: 10.0, y: 10.0 }).map(|points| points.len()), Some(1000));
+
+    ntree.insert(Vec2 { x: 10.5, y: 10.5 });
+    assert!(ntree.stats().max_depth > 1);
+}
+
+// Splits buckets holding more than one point, until they are narrower
+// than the minimum width.
+#[derive(Clone)]
+struct MinWidth(f64);
+
+impl SplitPolicy<QuadTreeRegion> for MinWidth {
+    fn should_split(&self, region: &QuadTreeRegion, _: usize, len: usize) -> bool {
+        len > 1 && region.width > self.0
+    }
+
+    fn should_merge(&self, _: &QuadTreeRegion, _: usize, len: usize) -> bool {
+        len <= 1
+    }
+}
+
+#[test]
+fn test_custom_policy() {
+    let mut ntree = NTree::with_policy(QuadTreeRegion::square(0.0, 0.0, 100.0), MinWidth(20.0));
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+    ntree.insert(Vec2 { x: 11.0, y: 11.0 });
+    ntree.insert(Vec2 { x: 80.0, y: 80.0 });
+
+    // Splits stop at quadrants 12.5 wide, which overflow instead.
+    let stats = ntree.stats();
+    assert_eq!(stats.max_depth, 3);
+    assert_eq!(ntree.nearby(&Vec2 { x: 10.0, y: 10.0 }),
+               Some(&[Vec2 { x: 10.0, y: 10.0 }, Vec2 { x: 11.0, y: 11.0 }] as &[_]));
+
+    // Removing a point lets the policy merge the branches back.
+    assert!(ntree.remove(&Vec2 { x: 11.0, y: 11.0 }));
+    assert_eq!(ntree.stats().max_depth, 1);
+}
+
+#[test]
+fn test_len() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    assert!(ntree.is_empty());
+    assert_eq!(ntree.len(), 0);
+
+    for p in scattered(20, 0.0) { ntree.insert(p); }
+    assert!(!ntree.is_empty());
+    assert_eq!(ntree.len(), 20);
+
+    // Points outside the tree are not counted.
+    ntree.insert(Vec2 { x: 200.0, y: 0.0 });
+    assert_eq!(ntree.len(), 20);
+
+    for p in scattered(20, 0.0) { ntree.remove(&p); }
+    assert!(ntree.is_empty());
+}
+
+#[test]
+fn test_stats() {
+    use Stats;
+
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    assert_eq!(ntree.stats(), Stats {
+        points: 0,
+        nodes: 5,
+        leaves: 4,
+        max_depth: 1,
+        avg_depth: 1.0,
+        bucket_fill: vec![4]
+    });
+
+    // Fill the bottom left quadrant until it splits.
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+    ntree.insert(Vec2 { x: 40.0, y: 10.0 });
+    ntree.insert(Vec2 { x: 40.0, y: 40.0 });
+    ntree.insert(Vec2 { x: 75.0, y: 75.0 });
+
+    assert_eq!(ntree.stats(), Stats {
+        points: 4,
+        nodes: 9,
+        leaves: 7,
+        max_depth: 2,
+        avg_depth: 11.0 / 7.0,
+        bucket_fill: vec![3, 4]
+    });
+}
+
+#[test]
+fn test_iter() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    let mut points: Vec<Vec2> = scattered(50, 0.0).collect();
+    for p in &points { ntree.insert(*p); }
+    points.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+
+    let mut found: Vec<Vec2> = ntree.iter().cloned().collect();
+    found.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+    assert_eq!(found, points);
+
+    assert_eq!((&ntree).into_iter().count(), 50);
+
+    let mut owned: Vec<Vec2> = ntree.into_iter().collect();
+    owned.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+    assert_eq!(owned, points);
+}
+
+#[test]
+fn test_iter_mut() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    for p in scattered(50, 0.5) { ntree.insert(p); }
+
+    // Nudge every point without leaving its bucket.
+    for p in &mut ntree {
+        p.x -= 0.1;
+    }
+    for p in ntree.iter_mut() {
+        p.y -= 0.1;
+    }
+
+    for moved in scattered(50, 0.4) {
+        assert!(ntree.nearby(&moved).unwrap().contains(&moved));
+        assert!(ntree.remove(&moved));
+    }
+    assert!(ntree.is_empty());
+}
+
+#[test]
+fn test_drain() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    for p in scattered(50, 0.0) { ntree.insert(p); }
+
+    assert_eq!(ntree.drain().count(), 50);
+    assert!(ntree.is_empty());
+    assert_eq!(ntree.stats().nodes, 1);
+
+    // The emptied tree still accepts and splits on new points.
+    for p in scattered(50, 0.0) { ntree.insert(p); }
+    assert_eq!(ntree.len(), 50);
+    assert!(ntree.nearby(&Vec2 { x: 0.0, y: 0.0 }).unwrap().len() <= 4);
+}
+
+#[test]
+fn test_from_points() {
+    let points: Vec<Vec2> = (0..500)
+        .map(|i| Vec2 { x: (i * 37 % 101) as f64, y: (i * 61 % 103) as f64 })
+        .collect();
+    let ntree = NTree::from_points(QuadTreeRegion::square(0.0, 0.0, 100.0), 4, points.clone());
+
+    // Points outside the region are left out.
+    let mut expected: Vec<&Vec2> = points.iter().filter(|p| p.x <= 100.0 && p.y <= 100.0).collect();
+    let mut found: Vec<&Vec2> = ntree.iter().collect();
+    expected.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+    found.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+    assert_eq!(found, expected);
+
+    // No bucket is over the limit, and every point can be found again.
+    assert!(ntree.stats().bucket_fill.len() <= 5);
+    for p in &expected {
+        assert!(ntree.nearby(p).unwrap().contains(p));
+    }
+}
+
+#[test]
+fn test_extend() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+    ntree.extend(scattered(100, 0.0));
+    ntree.extend(vec![Vec2 { x: 10.0, y: 10.0 }; 100]);
+
+    assert_eq!(ntree.len(), 201);
+    assert_eq!(ntree.nearby(&Vec2 { x: 10.0, y: 10.0 }).unwrap().len(), 101);
+    assert!(ntree.stats().max_depth <= ::DEFAULT_MAX_DEPTH as usize);
+}
+
+#[test]
+fn test_relocate() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    for p in scattered(50, 0.0) { ntree.insert(p); }
+
+    // Within the same bucket.
+    assert!(ntree.relocate(&Vec2 { x: 37.0, y: 61.0 }, Vec2 { x: 37.5, y: 61.5 }));
+    assert!(ntree.nearby(&Vec2 { x: 37.5, y: 61.5 }).unwrap().contains(&Vec2 { x: 37.5, y: 61.5 }));
+
+    // Across the tree.
+    assert!(ntree.relocate(&Vec2 { x: 37.5, y: 61.5 }, Vec2 { x: 99.0, y: 1.0 }));
+    assert!(ntree.nearby(&Vec2 { x: 99.0, y: 1.0 }).unwrap().contains(&Vec2 { x: 99.0, y: 1.0 }));
+    assert!(!ntree.iter().any(|p| *p == Vec2 { x: 37.5, y: 61.5 }));
+    assert_eq!(ntree.len(), 50);
+
+    // Missing points and positions outside the tree are left alone.
+    assert!(!ntree.relocate(&Vec2 { x: 37.0, y: 61.0 }, Vec2 { x: 1.0, y: 1.0 }));
+    assert!(!ntree.relocate(&Vec2 { x: 99.0, y: 1.0 }, Vec2 { x: 101.0, y: 1.0 }));
+    assert!(ntree.nearby(&Vec2 { x: 99.0, y: 1.0 }).unwrap().contains(&Vec2 { x: 99.0, y: 1.0 }));
+    assert_eq!(ntree.len(), 50);
+}
+
+#[test]
+fn test_relocate_onto_split_line() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    ntree.insert(Vec2 { x: 60.0, y: 60.0 });
+
+    // (50, 50) lies in every quadrant, but belongs to the first.
+    assert!(ntree.relocate(&Vec2 { x: 60.0, y: 60.0 }, Vec2 { x: 50.0, y: 50.0 }));
+    assert_eq!(ntree.len(), 1);
+    assert_eq!(ntree.nearby(&Vec2 { x: 50.0, y: 50.0 }), Some(&[Vec2 { x: 50.0, y: 50.0 }] as &[_]));
+    assert!(ntree.remove(&Vec2 { x: 50.0, y: 50.0 }));
+    assert!(ntree.is_empty());
+
+    // And deeper down, onto a line splitting a nested branch.
+    for p in scattered(50, 0.0) { ntree.insert(p); }
+    assert!(ntree.relocate(&Vec2 { x: 37.0, y: 61.0 }, Vec2 { x: 25.0, y: 75.0 }));
+    assert!(ntree.nearby(&Vec2 { x: 25.0, y: 75.0 }).unwrap().contains(&Vec2 { x: 25.0, y: 75.0 }));
+    assert!(ntree.remove(&Vec2 { x: 25.0, y: 75.0 }));
+    assert_eq!(ntree.len(), 49);
+}
+
+#[test]
+fn test_relocate_all() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    let points: Vec<Vec2> = scattered(50, 0.0).collect();
+    for p in &points { ntree.insert(*p); }
+
+    // Gather everything into one corner, then spread it back out.
+    let gathered: Vec<Vec2> = points.iter().map(|p| Vec2 { x: p.x / 10.0, y: p.y / 10.0 }).collect();
+    assert_eq!(ntree.relocate_all(points.iter().cloned().zip(gathered.iter().cloned())), 50);
+    assert_eq!(ntree.range_query(&QuadTreeRegion::square(0.0, 0.0, 10.0)).count(), 50);
+
+    assert_eq!(ntree.relocate_all(gathered.into_iter().zip(points.iter().cloned())), 50);
+    for p in &points {
+        assert!(ntree.nearby(p).unwrap().contains(p));
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    for p in scattered(100, 0.0) { ntree.insert(p); }
+
+    let json = serde_json::to_string(&ntree).unwrap();
+    let loaded: NTree<QuadTreeRegion, Vec2> = serde_json::from_str(&json).unwrap();
+
+    // The same shape, without re-splitting anything.
+    assert_eq!(loaded.stats(), ntree.stats());
+    assert_eq!(loaded.len(), 100);
+    assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+    assert_eq!(loaded.range_query(&QuadTreeRegion::square(20.0, 20.0, 30.0)).count(),
+               ntree.range_query(&QuadTreeRegion::square(20.0, 20.0, 30.0)).count());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_rebuilds_summaries() {
+    let mut ntree: NTree<_, _, _, Tally> = NTree::summarized(QuadTreeRegion::square(0.0, 0.0, 100.0), BucketLimit::new(4));
+    for p in scattered(100, 0.0) { ntree.insert(p); }
+
+    let json = serde_json::to_string(&ntree).unwrap();
+    let loaded: NTree<QuadTreeRegion, Vec2, BucketLimit, Tally> = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.summary(), ntree.summary());
+
+    let query = QuadTreeRegion::square(20.0, 20.0, 50.0);
+    assert_eq!(loaded.range_aggregate(&query), ntree.range_aggregate(&query));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_validates_points() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+
+    // Move the point into another quadrant behind the tree's back.
+    let mut value = serde_json::to_value(&ntree).unwrap();
+    value["nodes"][1]["Bucket"]["points"][0]["x"] = 90.0.into();
+    assert!(serde_json::from_value::<NTree<QuadTreeRegion, Vec2>>(value).is_err());
+
+    // A point on a split line belongs to the first quadrant sharing it,
+    // even though the others contain it too.
+    let mut value = serde_json::to_value(&ntree).unwrap();
+    value["nodes"][1]["Bucket"]["points"] = serde_json::json!([]);
+    value["nodes"][3]["Bucket"]["points"] = serde_json::json!([{ "x": 50.0, "y": 10.0 }]);
+    assert!(serde_json::from_value::<NTree<QuadTreeRegion, Vec2>>(value).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_validates_branches() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+    let value = serde_json::to_value(&ntree).unwrap();
+    assert!(serde_json::from_value::<NTree<QuadTreeRegion, Vec2>>(value.clone()).is_ok());
+
+    // A branch must have a child for each of its sub-regions.
+    let mut fewer = value.clone();
+    fewer["nodes"][0]["Branch"]["children"] = 3.into();
+    assert!(serde_json::from_value::<NTree<QuadTreeRegion, Vec2>>(fewer).is_err());
+
+    // And every node but the root must be the child of a branch.
+    let mut extra = value.clone();
+    let bucket = extra["nodes"][1].clone();
+    extra["nodes"].as_array_mut().unwrap().push(bucket);
+    assert!(serde_json::from_value::<NTree<QuadTreeRegion, Vec2>>(extra).is_err());
+
+    // Buckets must be as deep as they are below the root.
+    let mut deeper = value.clone();
+    deeper["nodes"][2]["Bucket"]["depth"] = 2.into();
+    assert!(serde_json::from_value::<NTree<QuadTreeRegion, Vec2>>(deeper).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_shares_policy() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+    let value = serde_json::to_value(&ntree).unwrap();
+
+    // The policy is written once, rather than by every bucket.
+    assert_eq!(value["policy"]["bucket_limit"], 4);
+    assert!(value["nodes"].as_array().unwrap().iter().all(|node| node["Bucket"].get("policy").is_none()));
+
+    // So a changed policy applies to every bucket, and no bucket holds
+    // more than one point.
+    let mut tighter = value.clone();
+    tighter["policy"]["bucket_limit"] = 1.into();
+    let mut loaded: NTree<QuadTreeRegion, Vec2> = serde_json::from_value(tighter).unwrap();
+    for p in &[Vec2 { x: 20.0, y: 20.0 }, Vec2 { x: 60.0, y: 60.0 }, Vec2 { x: 70.0, y: 70.0 }] {
+        loaded.insert(*p);
+    }
+    assert_eq!(loaded.stats().bucket_fill.len(), 2);
+
+    // And a bucket can't bring a policy of its own.
+    let mut own = value.clone();
+    own["nodes"][1]["Bucket"]["policy"] = serde_json::json!({ "bucket_limit": 100, "max_depth": 10 });
+    assert!(serde_json::from_value::<NTree<QuadTreeRegion, Vec2>>(own).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_max_depth() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    for _ in 0..10 {
+        ntree.insert(Vec2 { x: 12.5, y: 12.5 });
+    }
+    assert_eq!(ntree.stats().max_depth, ::DEFAULT_MAX_DEPTH as usize);
+
+    let json = serde_json::to_string(&ntree).unwrap();
+    let loaded: NTree<QuadTreeRegion, Vec2> = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.stats(), ntree.stats());
+    assert_eq!(loaded.nearby(&Vec2 { x: 12.5, y: 12.5 }).unwrap().len(), 10);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_higher_dimensions() {
+    use regions::{BoxRegion, Point};
+
+    let mut ntree = NTree::new(BoxRegion::cube([0.0; 4], 10.0), 2);
+    for i in 0..10 {
+        ntree.insert(Point([i as f64, (i * 3 % 10) as f64, (i * 7 % 10) as f64, 5.0]));
+    }
+
+    let json = serde_json::to_string(&ntree).unwrap();
+    let loaded: NTree<BoxRegion<4>, Point<4>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.stats(), ntree.stats());
+    assert!(serde_json::from_str::<Point<4>>("[1.0, 2.0, 3.0]").is_err());
+}
+
+#[test]
+fn test_map() {
+    use NTreeMap;
+
+    let mut map = NTreeMap::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    for (i, position) in scattered(50, 0.0).enumerate() {
+        assert_eq!(map.insert(position, format!("entity {}", i)), Ok(None));
+    }
+    assert_eq!(map.len(), 50);
+
+    let position = Vec2 { x: 37.0, y: 61.0 };
+    assert_eq!(map.get(&position), Some(&"entity 1".to_string()));
+    map.get_mut(&position).unwrap().push_str(" (moved)");
+    assert_eq!(map.get(&position), Some(&"entity 1 (moved)".to_string()));
+
+    // Inserting at an occupied position replaces the value.
+    assert_eq!(map.insert(position, "replacement".to_string()), Ok(Some("entity 1 (moved)".to_string())));
+    assert_eq!(map.len(), 50);
+
+    // Positions outside the map are handed back.
+    assert_eq!(map.insert(Vec2 { x: 150.0, y: 0.0 }, "outside".to_string()),
+               Err((Vec2 { x: 150.0, y: 0.0 }, "outside".to_string())));
+
+    assert_eq!(map.remove(&position), Some("replacement".to_string()));
+    assert_eq!(map.remove(&position), None);
+    assert!(!map.contains_key(&position));
+    assert_eq!(map.len(), 49);
+    assert_eq!(map.iter().count(), 49);
+}
+
+#[test]
+fn test_map_range_query() {
+    use NTreeMap;
+
+    let mut map = NTreeMap::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    for (i, position) in scattered(100, 0.0).enumerate() { map.insert(position, i).unwrap(); }
+
+    let query = QuadTreeRegion { x: 20.0, y: 10.0, width: 30.0, height: 45.0 };
+    let mut found: Vec<usize> = map.range_query(&query).map(|(_, &i)| i).collect();
+    let mut expected: Vec<usize> = map.iter().filter(|&(p, _)| query.contains(p)).map(|(_, &i)| i).collect();
+    found.sort();
+    expected.sort();
+
+    assert!(!expected.is_empty());
+    assert_eq!(found, expected);
+
+    // Other shapes are queries over positions too.
+    let circle = Circle { center: Vec2 { x: 30.0, y: 60.0 }, radius: 25.0 };
+    let mut found: Vec<usize> = map.range_query(&circle).map(|(_, &i)| i).collect();
+    let mut expected: Vec<usize> = map.iter().filter(|&(p, _)| ::Query::matches(&circle, p)).map(|(_, &i)| i).collect();
+    found.sort();
+    expected.sort();
+    assert!(!expected.is_empty());
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn test_persistent() {
+    use PersistentNTree;
+
+    let empty = PersistentNTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    let points: Vec<Vec2> = scattered(50, 0.5).collect();
+
+    let mut versions = vec![empty.clone()];
+    for p in &points {
+        let next = versions.last().unwrap().insert(*p).unwrap();
+        versions.push(next);
+    }
+    assert!(empty.insert(Vec2 { x: 200.0, y: 0.0 }).is_none());
+
+    // Every snapshot still holds exactly the points inserted before it.
+    let query = QuadTreeRegion::square(0.0, 0.0, 50.0);
+    for (n, version) in versions.iter().enumerate() {
+        assert_eq!(version.len(), n);
+        assert_eq!(version.iter().count(), n);
+        assert_eq!(version.range_query(&query).count(),
+                   points[..n].iter().filter(|p| query.contains(p)).count());
+    }
+
+    // Other shapes are queries too.
+    let full = versions.last().unwrap();
+    let circle = Circle { center: Vec2 { x: 30.0, y: 60.0 }, radius: 25.0 };
+    assert_eq!(full.range_query(&circle).count(), points.iter().filter(|p| ::Query::matches(&circle, *p)).count());
+    assert_eq!(full.range_query(&LeftOf(40.0)).count(), points.iter().filter(|p| p.x < 40.0).count());
+
+    let removed = full.remove(&points[0]).unwrap();
+    assert_eq!(removed.len(), 49);
+    assert_eq!(removed.iter().count(), 49);
+    assert_eq!(full.len(), 50);
+    assert!(removed.remove(&points[0]).is_none());
+    assert!(full.nearby(&points[0]).unwrap().contains(&points[0]));
+
+    // Removing everything merges the tree back down to one bucket.
+    let mut drained = full.clone();
+    for (n, p) in points.iter().enumerate() {
+        drained = drained.remove(p).unwrap();
+        assert_eq!(drained.len(), points.len() - n - 1);
+    }
+    assert!(drained.is_empty());
+    assert_eq!(drained.nearby(&points[0]), Some(&[] as &[_]));
+}
+
+#[test]
+fn test_persistent_snapshots_across_threads() {
+    use std::thread;
+    use PersistentNTree;
+
+    let mut ntree = PersistentNTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    for i in 0..100 {
+        ntree = ntree.insert(Vec2 { x: i as f64, y: i as f64 }).unwrap();
+    }
+
+    let snapshot = ntree.clone();
+    let reader = thread::spawn(move || snapshot.iter().count());
+
+    for i in 0..100 {
+        ntree = ntree.insert(Vec2 { x: i as f64 + 0.5, y: i as f64 }).unwrap();
+    }
+    assert_eq!(reader.join().unwrap(), 100);
+    assert_eq!(ntree.len(), 200);
+}
+
+#[test]
+fn test_concurrent() {
+    use std::sync::Arc;
+    use std::thread;
+    use ConcurrentNTree;
+
+    let ntree = Arc::new(ConcurrentNTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4));
+    let point = |t: usize, i: usize| Vec2 { x: ((i * 37 + t * 11) % 100) as f64 + t as f64 / 8.0,
+                                            y: ((i * 61) % 100) as f64 + 0.5 };
+
+    // Writers race to fill, and so split, the same buckets.
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let ntree = ntree.clone();
+            thread::spawn(move || {
+                for i in 0..500 {
+                    assert!(ntree.insert(point(t, i)));
+                }
+            })
+        })
+        .collect();
+    let reader = {
+        let ntree = ntree.clone();
+        thread::spawn(move || {
+            for _ in 0..100 {
+                assert!(ntree.range_query(&QuadTreeRegion::square(0.0, 0.0, 100.0)).len() <= 2000);
+            }
+        })
+    };
+    for writer in writers { writer.join().unwrap(); }
+    reader.join().unwrap();
+
+    assert_eq!(ntree.len(), 2000);
+    let query = QuadTreeRegion::square(10.0, 20.0, 35.0);
+    let expected = (0..4).flat_map(|t| (0..500).map(move |i| point(t, i))).filter(|p| query.contains(p)).count();
+    assert_eq!(ntree.range_query(&query).len(), expected);
+    let circle = Circle { center: Vec2 { x: 30.0, y: 60.0 }, radius: 25.0 };
+    let expected = (0..4).flat_map(|t| (0..500).map(move |i| point(t, i))).filter(|p| ::Query::matches(&circle, p)).count();
+    assert_eq!(ntree.range_query(&circle).len(), expected);
+
+    // Removers race to empty, and so merge, them again.
+    let removers: Vec<_> = (0..4)
+        .map(|t| {
+            let ntree = ntree.clone();
+            thread::spawn(move || {
+                for i in 0..500 {
+                    assert!(ntree.remove(&point(t, i)));
+                }
+            })
+        })
+        .collect();
+    for remover in removers { remover.join().unwrap(); }
+
+    assert!(!ntree.remove(&point(0, 0)));
+    assert_eq!(ntree.len(), 0);
+    assert!(ntree.is_empty());
+    assert_eq!(ntree.nearby(&Vec2 { x: 50.0, y: 50.0 }), Some(vec![]));
+    assert!(!ntree.insert(Vec2 { x: 200.0, y: 0.0 }));
+}
+
+#[derive(Debug, PartialEq)]
+struct Sprite {
+    id: usize,
+    bounds: QuadTreeRegion
+}
+
+impl ::Bounded<QuadTreeRegion> for Sprite {
+    fn bounds(&self) -> QuadTreeRegion { self.bounds }
+}
+
+#[test]
+fn test_object_tree() {
+    use ObjectTree;
+
+    let mut tree = ObjectTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+
+    // Straddles the center, so stays at the root however much the tree splits.
+    let center = Sprite { id: 0, bounds: QuadTreeRegion::square(45.0, 45.0, 10.0) };
+    assert!(tree.insert(Sprite { id: 0, bounds: center.bounds }));
+    for i in 1..20 {
+        let offset = i as f64;
+        assert!(tree.insert(Sprite { id: i, bounds: QuadTreeRegion::square(offset, offset, 1.0) }));
+    }
+    assert!(!tree.insert(Sprite { id: 99, bounds: QuadTreeRegion::square(200.0, 0.0, 1.0) }));
+    assert_eq!(tree.len(), 20);
+
+    // Partly outside the tree, but still found outside it.
+    let edge = Sprite { id: 20, bounds: QuadTreeRegion::square(95.0, 0.0, 10.0) };
+    assert!(tree.insert(Sprite { id: 20, bounds: edge.bounds }));
+    assert_eq!(tree.stab(&Vec2 { x: 102.0, y: 5.0 }).collect::<Vec<_>>(), vec![&edge]);
+    assert!(tree.remove(&edge));
+
+    // A small query in one quadrant still finds the straddling sprite.
+    assert_eq!(tree.range_query(&QuadTreeRegion::square(54.0, 54.0, 0.5)).collect::<Vec<_>>(),
+               vec![&center]);
+    assert_eq!(tree.stab(&Vec2 { x: 46.0, y: 54.0 }).collect::<Vec<_>>(), vec![&center]);
+
+    let mut ids: Vec<usize> = tree.stab(&Vec2 { x: 5.5, y: 5.5 }).map(|s| s.id).collect();
+    ids.sort();
+    assert_eq!(ids, vec![5]);
+
+    assert!(tree.remove(&center));
+    assert!(!tree.remove(&center));
+    for i in 1..20 {
+        let offset = i as f64;
+        assert!(tree.remove(&Sprite { id: i, bounds: QuadTreeRegion::square(offset, offset, 1.0) }));
+    }
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn test_object_tree_matches_brute_force() {
+    use ObjectTree;
+
+    let mut rng = Xorshift(0x9E3779B97F4A7C15);
+    let mut tree = ObjectTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    let mut sprites = vec![];
+    for id in 0..300 {
+        let size = rng.next_f64() * rng.next_f64() * 30.0;
+        let bounds = QuadTreeRegion {
+            x: rng.next_f64() * (100.0 - size),
+            y: rng.next_f64() * (100.0 - size),
+            width: size,
+            height: size
+        };
+        tree.insert(Sprite { id, bounds });
+        sprites.push(Sprite { id, bounds });
+    }
+
+    for _ in 0..100 {
+        let query = QuadTreeRegion {
+            x: rng.next_f64() * 100.0,
+            y: rng.next_f64() * 100.0,
+            width: rng.next_f64() * 20.0,
+            height: rng.next_f64() * 20.0
+        };
+        let mut found: Vec<usize> = tree.range_query(&query).map(|s| s.id).collect();
+        let mut expected: Vec<usize> = sprites.iter().filter(|s| s.bounds.overlaps(&query)).map(|s| s.id).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        let point = Vec2 { x: query.x, y: query.y };
+        let mut found: Vec<usize> = tree.stab(&point).map(|s| s.id).collect();
+        let mut expected: Vec<usize> = sprites.iter().filter(|s| s.bounds.contains(&point)).map(|s| s.id).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    for sprite in &sprites {
+        assert!(tree.remove(sprite));
+    }
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn test_nearby() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+
+    // Bottom left corner
+    ntree.insert(Vec2 { x: 30.0, y: 30.0 });
+    ntree.insert(Vec2 { x: 20.0, y: 20.0 });
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+
+    // Top right corner
+    ntree.insert(Vec2 { x: 75.0, y: 75.0 });
+
+    // Top left corner
+    ntree.insert(Vec2 { x: 40.0, y: 70.0 });
+
+    // Bottom right corner
+    ntree.insert(Vec2 { x: 80.0, y: 20.0 });
+
+    // Bottom left corner
+    assert_eq!(ntree.nearby(&Vec2 { x: 40.0, y: 40.0 }),
+        Some(&[Vec2 { x: 30.0, y: 30.0 },
+              Vec2 { x: 20.0, y: 20.0 },
+              Vec2 { x: 10.0, y: 10.0 }] as &[_]));
+
+    // Top right corner
+    assert_eq!(ntree.nearby(&Vec2 { x: 90.0, y: 90.0 }), Some(&[Vec2 { x: 75.0, y: 75.0 }] as &[_]));
+
+    // Top left corner
+    assert_eq!(ntree.nearby(&Vec2 { x: 20.0, y: 80.0 }), Some(&[Vec2 { x: 40.0, y: 70.0 }] as &[_]));
+
+    // Bottom right corner
+    assert_eq!(ntree.nearby(&Vec2 { x: 94.0, y: 12.0 }), Some(&[Vec2 { x: 80.0, y: 20.0 }] as &[_]));
+}
+
+#[test]
+fn test_range_query() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+
+    // Inside (y < 40)
+    ntree.insert(Vec2 { x: 30.0, y: 30.0 });
+    ntree.insert(Vec2 { x: 20.0, y: 20.0 });
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+    ntree.insert(Vec2 { x: 60.0, y: 20.0 });
+
+    // Outside (y > 40)
+    ntree.insert(Vec2 { x: 60.0, y: 59.0 });
+    ntree.insert(Vec2 { x: 60.0, y: 45.0 });
+
+    assert_eq!(ntree.range_query(&QuadTreeRegion { x: 0.0, y: 0.0, width: 100.0, height: 40.0 })
+                   .cloned().collect::<Vec<Vec2>>(),
+               vec![Vec2 { x: 30.0, y: 30.0 },
+                    Vec2 { x: 20.0, y: 20.0 },
+                    Vec2 { x: 10.0, y: 10.0 },
+                    Vec2 { x: 60.0, y: 20.0 }]);
+}
+
+#[test]
+fn test_k_nearest() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+
+    // Same bucket as the query point, but far away.
+    ntree.insert(Vec2 { x: 5.0, y: 5.0 });
+    ntree.insert(Vec2 { x: 10.0, y: 45.0 });
+
+    // Just across the bucket boundaries.
+    ntree.insert(Vec2 { x: 52.0, y: 49.0 });
+    ntree.insert(Vec2 { x: 49.0, y: 53.0 });
+    ntree.insert(Vec2 { x: 90.0, y: 90.0 });
+
+    assert_eq!(ntree.k_nearest(&Vec2 { x: 48.0, y: 48.0 }, 3, &Euclidean),
+               vec![&Vec2 { x: 52.0, y: 49.0 },
+                    &Vec2 { x: 49.0, y: 53.0 },
+                    &Vec2 { x: 10.0, y: 45.0 }]);
+
+    assert_eq!(ntree.k_nearest(&Vec2 { x: 48.0, y: 48.0 }, 10, &Euclidean).len(), 5);
+}
+
+#[test]
+fn test_nearest_neighbors_ordered() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    let points: Vec<Vec2> = scattered(200, 0.0).collect();
+    for p in &points { ntree.insert(*p); }
+
+    let target = Vec2 { x: 33.0, y: 71.0 };
+    let distance = |p: &Vec2| Metric::<Vec2, QuadTreeRegion>::distance(&Euclidean, &target, p);
+    let found: Vec<f64> = ntree.nearest_neighbors(&target, &Euclidean)
+        .map(distance)
+        .collect();
+
+    let mut expected: Vec<f64> = points.iter().map(distance).collect();
+    expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn test_metrics() {
+    let a = Vec2 { x: 1.0, y: 2.0 };
+    let b = Vec2 { x: 4.0, y: 6.0 };
+    let r = QuadTreeRegion::square(3.0, 3.0, 1.0);
+
+    assert_eq!(Metric::<Vec2, QuadTreeRegion>::distance(&Euclidean, &a, &b), 5.0);
+    assert_eq!(Metric::<Vec2, QuadTreeRegion>::distance(&Manhattan, &a, &b), 7.0);
+    assert_eq!(Metric::<Vec2, QuadTreeRegion>::distance(&Chebyshev, &a, &b), 4.0);
+
+    assert_eq!(Euclidean.min_distance(&a, &r), 5.0f64.sqrt());
+    assert_eq!(Manhattan.min_distance(&a, &r), 3.0);
+    assert_eq!(Chebyshev.min_distance(&a, &r), 2.0);
+
+    // Zero inside the region.
+    assert_eq!(Euclidean.min_distance(&Vec2 { x: 3.5, y: 3.5 }, &r), 0.0);
+}
+
+#[test]
+fn test_k_nearest_by_metric() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    ntree.insert(Vec2 { x: 60.0, y: 60.0 });
+    ntree.insert(Vec2 { x: 53.0, y: 39.0 });
+    ntree.insert(Vec2 { x: 20.0, y: 20.0 });
+
+    let target = Vec2 { x: 50.0, y: 50.0 };
+
+    // Diagonal neighbors win under Chebyshev, axis-aligned ones under Manhattan.
+    assert_eq!(ntree.k_nearest(&target, 1, &Chebyshev), vec![&Vec2 { x: 60.0, y: 60.0 }]);
+    assert_eq!(ntree.k_nearest(&target, 1, &Manhattan), vec![&Vec2 { x: 53.0, y: 39.0 }]);
+}
+
+#[test]
+fn test_within_radius() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 2);
+    let points: Vec<Vec2> = scattered(200, 0.0).collect();
+    for p in &points { ntree.insert(*p); }
+
+    let target = Vec2 { x: 48.0, y: 52.0 };
+    let distance = |p: &&Vec2| Metric::<Vec2, QuadTreeRegion>::distance(&Euclidean, &target, p);
+
+    let mut found: Vec<&Vec2> = ntree.within_radius(&target, 15.0, &Euclidean).collect();
+    let mut expected: Vec<&Vec2> = points.iter().filter(|p| distance(p) <= 15.0).collect();
+    found.sort_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap());
+    expected.sort_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap());
+
+    assert!(!expected.is_empty());
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn test_within_radius_boundary() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    ntree.insert(Vec2 { x: 50.0, y: 60.0 });
+    ntree.insert(Vec2 { x: 50.0, y: 61.0 });
+
+    assert_eq!(ntree.within_radius(&Vec2 { x: 50.0, y: 50.0 }, 10.0, &Manhattan).collect::<Vec<_>>(),
+               vec![&Vec2 { x: 50.0, y: 60.0 }]);
+}
+
+#[test]
+fn test_higher_dimensions() {
+    use regions::{BoxRegion, OctreeRegion, Point, Vec3};
+
+    let mut octree = NTree::new(OctreeRegion::cube(0.0, 0.0, 0.0, 10.0), 2);
+    for i in 0..10 {
+        octree.insert(Vec3 { x: i as f64, y: (i * 3 % 10) as f64, z: (i * 7 % 10) as f64 });
+    }
+    assert_eq!(octree.k_nearest(&Vec3 { x: 4.2, y: 2.1, z: 8.1 }, 1, &Euclidean),
+               vec![&Vec3 { x: 4.0, y: 2.0, z: 8.0 }]);
+
+    let mut ntree = NTree::new(BoxRegion::cube([0.0; 4], 10.0), 2);
+    for i in 0..10 {
+        ntree.insert(Point([i as f64, (i * 3 % 10) as f64, (i * 7 % 10) as f64, 5.0]));
+    }
+    assert_eq!(ntree.range_query(&BoxRegion::new([0.0, 0.0, 0.0, 0.0], [10.0, 5.0, 5.0, 10.0])).count(), 2);
+}
+
+// A small xorshift generator, so the randomized tests below are
+// reproducible and don't need the bench-only rand dependency.
+struct Xorshift(u64);
+
+impl Xorshift {
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[test]
+fn test_range_query_matches_brute_force() {
+    let mut rng = Xorshift(0x2545F4914F6CDD1D);
+
+    for &bucket_limit in &[1, 4, 16] {
+        let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), bucket_limit);
+        let points: Vec<Vec2> = (0..500)
+            .map(|_| Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 })
+            .collect();
+        for p in &points { ntree.insert(*p); }
+
+        for _ in 0..200 {
+            let query = QuadTreeRegion {
+                x: rng.next_f64() * 120.0 - 10.0,
+                y: rng.next_f64() * 120.0 - 10.0,
+                width: rng.next_f64() * rng.next_f64() * 100.0,
+                height: rng.next_f64() * rng.next_f64() * 100.0
+            };
+
+            let mut found: Vec<&Vec2> = ntree.range_query(&query).collect();
+            let mut expected: Vec<&Vec2> = points.iter().filter(|p| query.contains(p)).collect();
+            found.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+            expected.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+            assert_eq!(found, expected, "query {:?}", query);
+        }
+    }
+}
+
+#[test]
+fn test_range_query_inside_single_node() {
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+    ntree.insert(Vec2 { x: 30.0, y: 30.0 });
+
+    // Entirely inside the bottom left quadrant, touching none of its corners.
+    assert_eq!(ntree.range_query(&QuadTreeRegion::square(5.0, 5.0, 10.0)).collect::<Vec<_>>(),
+               vec![&Vec2 { x: 10.0, y: 10.0 }]);
+
+    // A cross-shaped overlap, with no corner of either inside the other.
+    assert_eq!(ntree.range_query(&QuadTreeRegion { x: 20.0, y: -10.0, width: 20.0, height: 200.0 })
+                   .collect::<Vec<_>>(),
+               vec![&Vec2 { x: 30.0, y: 30.0 }]);
+}
+
+// A circle, which can only be searched for through `Query`.
+struct Circle {
+    center: Vec2,
+    radius: f64
+}
+
+impl ::Query<QuadTreeRegion, Vec2> for Circle {
+    fn may_overlap(&self, region: &QuadTreeRegion) -> bool {
+        // The distance from the center to the nearest point of the region.
+        let dx = (region.x - self.center.x).max(self.center.x - (region.x + region.width)).max(0.0);
+        let dy = (region.y - self.center.y).max(self.center.y - (region.y + region.height)).max(0.0);
+        dx * dx + dy * dy <= self.radius * self.radius
+    }
+
+    fn matches(&self, point: &Vec2) -> bool {
+        let (dx, dy) = (point.x - self.center.x, point.y - self.center.y);
+        dx * dx + dy * dy <= self.radius * self.radius
+    }
+}
+
+// Everything to the left of a vertical line.
+struct LeftOf(f64);
+
+impl ::Query<QuadTreeRegion, Vec2> for LeftOf {
+    fn may_overlap(&self, region: &QuadTreeRegion) -> bool { region.x < self.0 }
+    fn matches(&self, point: &Vec2) -> bool { point.x < self.0 }
+}
 
 #[test]
-fn test_contains() {
-    let ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
-    assert!(ntree.contains(&Vec2 { x: 50.0, y: 50.0 }));
+fn test_range_query_with_other_shapes() {
+    let mut rng = Xorshift(0x5DEECE66D);
+    let points: Vec<Vec2> = (0..500)
+        .map(|_| Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 })
+        .collect();
+    let ntree = NTree::from_points(QuadTreeRegion::square(0.0, 0.0, 100.0), 4, points.iter().cloned());
+
+    let circle = Circle { center: Vec2 { x: 30.0, y: 60.0 }, radius: 25.0 };
+    let found = ntree.range_query(&circle).count();
+    assert!(found > 0);
+    assert_eq!(found, points.iter().filter(|p| ::Query::matches(&circle, *p)).count());
+
+    assert_eq!(ntree.range_query(&LeftOf(40.0)).count(), points.iter().filter(|p| p.x < 40.0).count());
+    assert_eq!(ntree.range_query(&LeftOf(-1.0)).count(), 0);
+
+    // Regions are still queries in their own right.
+    let query = QuadTreeRegion::square(10.0, 10.0, 30.0);
+    assert_eq!(ntree.range_query(&query).count(), points.iter().filter(|p| query.contains(p)).count());
 }
 
 #[test]
-fn test_insert() {
+fn test_range_count_takes_covered_regions_whole() {
+    // Claims to cover the right half of the tree, but matches nothing,
+    // so only covered regions' points are counted.
+    struct RightHalf;
+
+    impl ::Query<QuadTreeRegion, Vec2> for RightHalf {
+        fn may_overlap(&self, _: &QuadTreeRegion) -> bool { true }
+        fn matches(&self, _: &Vec2) -> bool { false }
+        fn covers(&self, region: &QuadTreeRegion) -> bool { region.x >= 50.0 }
+    }
+
+    let points: Vec<Vec2> = scattered(100, 0.5).collect();
+    let ntree = NTree::from_points(QuadTreeRegion::square(0.0, 0.0, 100.0), 4, points.iter().cloned());
+    assert_eq!(ntree.range_count(&RightHalf), points.iter().filter(|p| p.x >= 50.0).count());
+    assert_eq!(ntree.range_query(&RightHalf).count(), 0);
+
+    // A region covering part of the tree counts the same points either way.
+    let query = QuadTreeRegion { x: 0.0, y: 0.0, width: 62.5, height: 100.0 };
+    assert!(query.contains_region(&QuadTreeRegion::square(0.0, 0.0, 50.0)));
+    assert_eq!(ntree.range_count(&query), points.iter().filter(|p| query.contains(p)).count());
+}
+
+#[test]
+fn test_range_count() {
+    let mut rng = Xorshift(0x853C49E6748FEA9B);
     let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
-    assert!(ntree.insert(Vec2 { x: 50.0, y: 50.0 }));
-    assert_eq!(ntree.nearby(&Vec2 { x: 40.0, y: 40.0 }), Some(&[Vec2 { x: 50.0, y: 50.0 }] as &[_]));
+    let mut points: Vec<Vec2> = (0..1000)
+        .map(|_| Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 })
+        .collect();
+    for p in &points { ntree.insert(*p); }
+
+    // Keep the cached counts busy with removals, moves and bulk inserts.
+    for p in points.drain(..300) { ntree.remove(&p); }
+    for p in points.iter_mut().take(300) {
+        let new = Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 };
+        assert!(ntree.relocate(p, new));
+        *p = new;
+    }
+    let more: Vec<Vec2> = (0..200)
+        .map(|_| Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 })
+        .collect();
+    ntree.extend(more.iter().cloned());
+    points.extend(more);
+
+    assert_eq!(ntree.len(), points.len());
+    assert_eq!(ntree.len(), ntree.iter().count());
+    assert_eq!(ntree.range_count(&QuadTreeRegion::square(0.0, 0.0, 100.0)), points.len());
+    assert_eq!(ntree.range_count(&QuadTreeRegion::square(200.0, 0.0, 10.0)), 0);
+    for _ in 0..100 {
+        let query = QuadTreeRegion {
+            x: rng.next_f64() * 100.0,
+            y: rng.next_f64() * 100.0,
+            width: rng.next_f64() * 60.0,
+            height: rng.next_f64() * 60.0
+        };
+        assert_eq!(ntree.range_count(&query), points.iter().filter(|p| query.contains(p)).count());
+    }
+    assert_eq!(ntree.range_count(&LeftOf(30.0)), points.iter().filter(|p| p.x < 30.0).count());
+
+    for p in &points { ntree.remove(p); }
+    assert!(ntree.is_empty());
+}
+
+// The number of points and the total of their rounded-down x, which add
+// up exactly in whatever order points are combined.
+#[derive(Clone, Debug, PartialEq)]
+struct Tally {
+    count: usize,
+    x_total: u64
+}
+
+impl ::Summary<Vec2> for Tally {
+    fn empty() -> Tally { Tally { count: 0, x_total: 0 } }
+    fn of(point: &Vec2) -> Tally { Tally { count: 1, x_total: point.x as u64 } }
+    fn combine(&self, other: &Tally) -> Tally {
+        Tally { count: self.count + other.count, x_total: self.x_total + other.x_total }
+    }
 }
 
+fn tally<'a, I: IntoIterator<Item=&'a Vec2>>(points: I) -> Tally {
+    points.into_iter().fold(Tally { count: 0, x_total: 0 }, |t, p| Tally { count: t.count + 1, x_total: t.x_total + p.x as u64 })
+}
 
 #[test]
-fn test_remove() {
+fn test_range_aggregate() {
+    let mut rng = Xorshift(0xDA942042E4DD58B5);
+    let mut ntree: NTree<_, _, _, Tally> = NTree::summarized(QuadTreeRegion::square(0.0, 0.0, 100.0), BucketLimit::new(4));
+    let mut points: Vec<Vec2> = (0..1000)
+        .map(|_| Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 })
+        .collect();
+    for p in &points { ntree.insert(*p); }
+    assert_eq!(*ntree.summary(), tally(&points));
+
+    // Summaries follow removals, which merge buckets, moves and bulk inserts.
+    for p in points.drain(..300) { ntree.remove(&p); }
+    for p in points.iter_mut().take(300) {
+        let new = Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 };
+        assert!(ntree.relocate(p, new));
+        *p = new;
+    }
+    let more: Vec<Vec2> = (0..200)
+        .map(|_| Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 })
+        .collect();
+    ntree.extend(more.iter().cloned());
+    points.extend(more);
+    assert_eq!(*ntree.summary(), tally(&points));
+
+    for _ in 0..100 {
+        let query = QuadTreeRegion {
+            x: rng.next_f64() * 100.0,
+            y: rng.next_f64() * 100.0,
+            width: rng.next_f64() * 60.0,
+            height: rng.next_f64() * 60.0
+        };
+        assert_eq!(ntree.range_aggregate(&query), tally(points.iter().filter(|p| query.contains(p))));
+    }
+    assert_eq!(ntree.range_aggregate(&LeftOf(30.0)), tally(points.iter().filter(|p| p.x < 30.0)));
+
+    let drained: Vec<Vec2> = ntree.drain().collect();
+    assert_eq!(drained.len(), points.len());
+    assert_eq!(*ntree.summary(), tally(&[]));
+}
+
+// The number of points and the sum of their positions, for the center
+// of mass of equal masses.
+#[derive(Clone, Debug, PartialEq)]
+struct Mass {
+    count: usize,
+    x: f64,
+    y: f64
+}
+
+impl ::Summary<Vec2> for Mass {
+    fn empty() -> Mass { Mass { count: 0, x: 0.0, y: 0.0 } }
+    fn of(point: &Vec2) -> Mass { Mass { count: 1, x: point.x, y: point.y } }
+    fn combine(&self, other: &Mass) -> Mass {
+        Mass { count: self.count + other.count, x: self.x + other.x, y: self.y + other.y }
+    }
+}
+
+impl ::Centered<Vec2> for Mass {
+    fn center(&self) -> Vec2 {
+        Vec2 { x: self.x / self.count as f64, y: self.y / self.count as f64 }
+    }
+}
+
+#[test]
+fn test_approximate() {
+    use {Approximation, Centered};
+
+    let mut rng = Xorshift(0x2F7A5C8E1B3D9046);
+    let points: Vec<Vec2> = (0..2000)
+        .map(|_| Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 })
+        .collect();
+    let mut ntree: NTree<_, _, _, Mass> = NTree::summarized(QuadTreeRegion::square(0.0, 0.0, 100.0), BucketLimit::new(4));
+    ntree.extend(points.iter().cloned());
+    let target = Vec2 { x: 12.5, y: 80.0 };
+
+    // With a theta of zero every node is opened.
+    assert!(ntree.approximate(&target, 0.0, &Euclidean).all(|a| match a {
+        Approximation::Point(_) => true,
+        Approximation::Node { .. } => false
+    }));
+    assert_eq!(ntree.approximate(&target, 0.0, &Euclidean).count(), points.len());
+
+    // Otherwise every point is counted once, and nodes pass the criterion.
+    let distance = |p: &Vec2| ((p.x - target.x).powi(2) + (p.y - target.y).powi(2)).sqrt();
+    let potential = |p: &Vec2, mass: f64| mass / distance(p);
+    let (mut counted, mut items, mut approximate) = (0, 0, 0.0);
+    for a in ntree.approximate(&target, 0.5, &Euclidean) {
+        items += 1;
+        match a {
+            Approximation::Point(p) => {
+                counted += 1;
+                approximate += potential(p, 1.0);
+            },
+            Approximation::Node { region, summary } => {
+                assert!(!region.contains(&target));
+                assert!(region.width < 0.5 * distance(&summary.center()));
+                counted += summary.count;
+                approximate += potential(&summary.center(), summary.count as f64);
+            }
+        }
+    }
+    assert_eq!(counted, points.len());
+    assert!(items < points.len() / 4);
+
+    let exact: f64 = points.iter().map(|p| potential(p, 1.0)).sum();
+    assert!((approximate - exact).abs() < exact * 0.01, "{} vs {}", approximate, exact);
+}
+
+#[test]
+fn test_arena_matches_ntree() {
+    use ArenaNTree;
+
+    let mut rng = Xorshift(0x9E3779B97F4A7C15);
+    let mut arena = ArenaNTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
     let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
-    ntree.insert(Vec2 { x: 50.0, y: 50.0 });
-    assert!(ntree.remove(&Vec2 { x: 50.0, y: 50.0 }));
-    assert_eq!(ntree.nearby(&Vec2 { x: 40.0, y: 40.0 }), Some(&[] as &[_]));
-    assert!(!ntree.remove(&Vec2 { x: 50.0, y: 50.0 }));
+    let points: Vec<Vec2> = (0..1000)
+        .map(|_| Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 })
+        .collect();
+    for p in &points {
+        assert!(arena.insert(*p));
+        ntree.insert(*p);
+    }
+    assert!(!arena.insert(Vec2 { x: 200.0, y: 0.0 }));
+    assert_eq!(arena.len(), 1000);
+    assert_eq!(arena.iter().count(), 1000);
+
+    // Removing half the points merges buckets, and re-inserting them
+    // reuses the freed nodes and blocks.
+    for p in points.iter().step_by(2) {
+        assert!(arena.remove(p));
+        ntree.remove(p);
+    }
+    assert!(!arena.remove(&points[0]));
+    for p in points.iter().step_by(4) {
+        arena.insert(*p);
+        ntree.insert(*p);
+    }
+    assert_eq!(arena.len(), ntree.len());
+
+    assert_eq!(sorted(arena.iter()), sorted(ntree.iter()));
+    for _ in 0..50 {
+        let query = QuadTreeRegion {
+            x: rng.next_f64() * 100.0,
+            y: rng.next_f64() * 100.0,
+            width: rng.next_f64() * 50.0,
+            height: rng.next_f64() * 50.0
+        };
+        assert_eq!(sorted(arena.range_query(&query)), sorted(ntree.range_query(&query)));
+
+        let circle = Circle { center: Vec2 { x: query.x, y: query.y }, radius: query.width };
+        assert_eq!(sorted(arena.range_query(&circle)), sorted(ntree.range_query(&circle)));
+    }
+    assert_eq!(arena.range_query(&LeftOf(40.0)).count(), ntree.range_query(&LeftOf(40.0)).count());
+    for p in points.iter().take(50) {
+        assert_eq!(sorted(arena.nearby(p).unwrap()), sorted(ntree.nearby(p).unwrap().iter()));
+    }
+
+    // Removing everything merges the tree back down to one bucket.
+    for p in points.iter().skip(1).step_by(2).chain(points.iter().step_by(4)) {
+        assert!(arena.remove(p));
+    }
+    assert!(arena.is_empty());
+    assert_eq!(arena.nearby(&points[0]).unwrap().count(), 0);
 }
 
+#[cfg(feature = "rayon")]
 #[test]
-fn test_nearby() {
+fn test_parallel_queries() {
+    use rayon::prelude::*;
+
+    let mut rng = Xorshift(0x5DEECE66D);
     let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    for _ in 0..2000 {
+        ntree.insert(Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 });
+    }
 
-    // Bottom left corner
-    ntree.insert(Vec2 { x: 30.0, y: 30.0 });
-    ntree.insert(Vec2 { x: 20.0, y: 20.0 });
-    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
+    assert_eq!(sorted(ntree.par_iter().collect::<Vec<_>>()), sorted(ntree.iter()));
 
-    // Top right corner
-    ntree.insert(Vec2 { x: 75.0, y: 75.0 });
+    let queries: Vec<QuadTreeRegion> = (0..50)
+        .map(|_| QuadTreeRegion::square(rng.next_f64() * 120.0 - 10.0, rng.next_f64() * 120.0 - 10.0,
+                                        rng.next_f64() * 60.0))
+        .collect();
+    let batch = ntree.par_range_queries(&queries);
+    for (query, found) in queries.iter().zip(batch) {
+        let expected = sorted(ntree.range_query(query));
+        assert_eq!(sorted(ntree.par_range_query(query).collect::<Vec<_>>()), expected);
+        assert_eq!(sorted(found), expected);
+    }
 
-    // Top left corner
-    ntree.insert(Vec2 { x: 40.0, y: 70.0 });
+    // Queries missing the tree entirely find nothing.
+    assert_eq!(ntree.par_range_query(&QuadTreeRegion::square(200.0, 200.0, 10.0)).count(), 0);
 
-    // Bottom right corner
-    ntree.insert(Vec2 { x: 80.0, y: 20.0 });
+    // Other shapes are queries too.
+    let circles: Vec<Circle> = (0..10)
+        .map(|_| Circle { center: Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 },
+                          radius: rng.next_f64() * 30.0 })
+        .collect();
+    let batch = ntree.par_range_queries(&circles);
+    for (circle, found) in circles.iter().zip(batch) {
+        let expected = sorted(ntree.range_query(circle));
+        assert_eq!(sorted(ntree.par_range_query(circle).collect::<Vec<_>>()), expected);
+        assert_eq!(sorted(found), expected);
+    }
+}
 
-    // Bottom left corner
-    assert_eq!(ntree.nearby(&Vec2 { x: 40.0, y: 40.0 }),
-        Some(&[Vec2 { x: 30.0, y: 30.0 },
-              Vec2 { x: 20.0, y: 20.0 },
-              Vec2 { x: 10.0, y: 10.0 }] as &[_]));
+// Write the n-tree to a fresh file in the temporary directory.
+#[cfg(feature = "mmap")]
+fn write_temp<R, P>(ntree: &NTree<R, P>, name: &str) -> ::std::path::PathBuf
+where R: ::FixedSize, P: PartialEq + ::FixedSize {
+    let path = ::std::env::temp_dir().join(format!("ntree-{}-{}.idx", ::std::process::id(), name));
+    ntree.write_to(::std::fs::File::create(&path).unwrap()).unwrap();
+    path
+}
 
-    // Top right corner
-    assert_eq!(ntree.nearby(&Vec2 { x: 90.0, y: 90.0 }), Some(&[Vec2 { x: 75.0, y: 75.0 }] as &[_]));
+#[cfg(feature = "mmap")]
+#[test]
+fn test_mapped_ntree() {
+    use MappedNTree;
 
-    // Top left corner
-    assert_eq!(ntree.nearby(&Vec2 { x: 20.0, y: 80.0 }), Some(&[Vec2 { x: 40.0, y: 70.0 }] as &[_]));
+    let mut rng = Xorshift(0x9E3779B97F4A7C15);
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    for _ in 0..500 {
+        ntree.insert(Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 });
+    }
 
-    // Bottom right corner
-    assert_eq!(ntree.nearby(&Vec2 { x: 94.0, y: 12.0 }), Some(&[Vec2 { x: 80.0, y: 20.0 }] as &[_]));
+    let path = write_temp(&ntree, "queries");
+    let mapped: MappedNTree<QuadTreeRegion, Vec2> = MappedNTree::open(&path).unwrap();
+    assert_eq!(mapped.len(), 500);
+
+    for _ in 0..50 {
+        let query = QuadTreeRegion::square(rng.next_f64() * 100.0, rng.next_f64() * 100.0, rng.next_f64() * 40.0);
+        assert_eq!(sorted(mapped.range_query(&query)), sorted(ntree.range_query(&query)));
+
+        let circle = Circle { center: Vec2 { x: query.x, y: query.y }, radius: query.width };
+        assert_eq!(sorted(mapped.range_query(&circle)), sorted(ntree.range_query(&circle)));
+
+        let point = Vec2 { x: rng.next_f64() * 100.0, y: rng.next_f64() * 100.0 };
+        assert_eq!(mapped.nearby(&point).unwrap().collect::<Vec<_>>(), ntree.nearby(&point).unwrap());
+        assert_eq!(mapped.k_nearest(&point, 5, &Euclidean),
+                   ntree.k_nearest(&point, 5, &Euclidean).into_iter().cloned().collect::<Vec<_>>());
+    }
+
+    assert_eq!(mapped.range_query(&LeftOf(40.0)).count(), ntree.range_query(&LeftOf(40.0)).count());
+    assert!(mapped.nearby(&Vec2 { x: 200.0, y: 0.0 }).is_none());
+    ::std::fs::remove_file(&path).unwrap();
 }
 
+#[cfg(feature = "mmap")]
 #[test]
-fn test_range_query() {
+fn test_mapped_ntree_rejects_bad_files() {
+    use regions::{OctreeRegion, Vec3};
+    use std::fs;
+    use std::io::ErrorKind;
+    use MappedNTree;
+
     let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 100.0), 4);
+    for p in scattered(20, 0.0) { ntree.insert(p); }
+    let path = write_temp(&ntree, "corrupt");
 
-    // Inside (y < 40)
-    ntree.insert(Vec2 { x: 30.0, y: 30.0 });
-    ntree.insert(Vec2 { x: 20.0, y: 20.0 });
-    ntree.insert(Vec2 { x: 10.0, y: 10.0 });
-    ntree.insert(Vec2 { x: 60.0, y: 20.0 });
+    // Wrong point and region types.
+    let err = MappedNTree::<OctreeRegion, Vec3>::open(&path).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
 
-    // Outside (y > 40)
-    ntree.insert(Vec2 { x: 60.0, y: 59.0 });
-    ntree.insert(Vec2 { x: 60.0, y: 45.0 });
+    // A flipped bit is caught by the checksum.
+    let mut bytes = fs::read(&path).unwrap();
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 1;
+    fs::write(&path, &bytes).unwrap();
+    let err = MappedNTree::<QuadTreeRegion, Vec2>::open(&path).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
 
-    assert_eq!(ntree.range_query(&QuadTreeRegion { x: 0.0, y: 0.0, width: 100.0, height: 40.0 })
-                   .map(|x| x.clone()).collect::<Vec<Vec2>>(),
-               vec![Vec2 { x: 30.0, y: 30.0 },
-                    Vec2 { x: 20.0, y: 20.0 },
-                    Vec2 { x: 10.0, y: 10.0 },
-                    Vec2 { x: 60.0, y: 20.0 }]);
+    // Unknown versions are refused.
+    bytes[middle] ^= 1;
+    bytes[8] = 99;
+    fs::write(&path, &bytes).unwrap();
+    let err = MappedNTree::<QuadTreeRegion, Vec2>::open(&path).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    fs::remove_file(&path).unwrap();
 }
 
 #[cfg(feature = "bench")]
@@ -131,74 +1461,169 @@ fn bench_range_query_large(b: &mut Bencher) {
     range_query_bench(b, 10000);
 }
 
-mod fixtures {
-    use {Region};
+// A fixed tree and set of query windows, each between half and all of
+// the tree wide, so most nodes they reach lie entirely within them.
+// Fixing them lets runs be compared with each other.
+#[cfg(feature = "bench")]
+fn large_windows() -> (NTree<QuadTreeRegion, Vec2>, Vec<QuadTreeRegion>) {
+    let mut rng = XorShiftRng::new_unseeded();
+    let points: Vec<Vec2> = (0..10000).map(|_| Vec2 { x: rng.gen(), y: rng.gen() }).collect();
+    let windows = (0..16)
+        .map(|_| {
+            let x: f64 = rng.gen::<f64>() * 0.5;
+            let y: f64 = rng.gen::<f64>() * 0.5;
+            QuadTreeRegion { x, y, width: 0.5 + x, height: 0.5 + y }
+        })
+        .collect();
+    (NTree::from_points(QuadTreeRegion::square(0.0, 0.0, 1.0), 4, points), windows)
+}
 
-    #[derive(Clone, Debug, PartialEq)]
-    pub struct QuadTreeRegion {
-        pub x: f64,
-        pub y: f64,
-        pub width: f64,
-        pub height: f64
-    }
+#[cfg(feature = "bench")]
+fn large_window_bench<Q, F>(b: &mut Bencher, query: F)
+where Q: ::Query<QuadTreeRegion, Vec2>, F: Fn(QuadTreeRegion) -> Q {
+    let (ntree, windows) = large_windows();
+    let queries: Vec<Q> = windows.into_iter().map(query).collect();
+    b.iter(|| queries.iter().map(|q| ntree.range_count(q)).sum::<usize>())
+}
 
-    #[derive(Debug, PartialEq, Clone)]
-    pub struct Vec2 {
-        pub x: f64, pub y:f64
-    }
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_range_count_large_window(b: &mut Bencher) {
+    large_window_bench(b, |r| r);
+}
 
-    impl QuadTreeRegion {
-        pub fn square(x: f64, y:f64, wh: f64) -> QuadTreeRegion {
-            QuadTreeRegion { x: x, y: y, width: wh, height: wh }
-        }
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_range_count_large_window_per_point(b: &mut Bencher) {
+    // The same query, without the full-containment shortcut.
+    struct PerPoint(QuadTreeRegion);
+
+    impl ::Query<QuadTreeRegion, Vec2> for PerPoint {
+        fn may_overlap(&self, region: &QuadTreeRegion) -> bool { region.overlaps(&self.0) }
+        fn matches(&self, point: &Vec2) -> bool { self.0.contains(point) }
     }
 
-    impl Region<Vec2> for QuadTreeRegion {
-        fn contains(&self, p: &Vec2) -> bool {
-            self.x <= p.x && self.y <= p.y && (self.x + self.width) >= p.x && (self.y + self.height) >= p.y
-        }
+    large_window_bench(b, PerPoint);
+}
 
-        fn split(&self) -> Vec<QuadTreeRegion> {
-            let halfwidth = self.width / 2.0;
-            let halfheight = self.height / 2.0;
-            vec![
-                QuadTreeRegion {
-                    x: self.x,
-                    y: self.y,
-                    width: halfwidth,
-                    height: halfheight
-                },
+#[cfg(feature = "bench")]
+fn random_points(n: usize) -> Vec<Vec2> {
+    let mut rng: XorShiftRng = random();
+    (0..n).map(|_| Vec2 { x: rng.gen(), y: rng.gen() }).collect()
+}
 
-                QuadTreeRegion {
-                    x: self.x,
-                    y: self.y + halfheight,
-                    width: halfwidth,
-                    height: halfheight
-                },
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_build_by_insert(b: &mut Bencher) {
+    let points = random_points(10000);
+    b.iter(|| {
+        let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 1.0), 4);
+        for p in &points { ntree.insert(*p); }
+        ntree
+    })
+}
 
-                QuadTreeRegion {
-                    x: self.x + halfwidth,
-                    y: self.y,
-                    width: halfwidth,
-                    height: halfheight
-                },
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_build_from_points(b: &mut Bencher) {
+    let points = random_points(10000);
+    b.iter(|| NTree::from_points(QuadTreeRegion::square(0.0, 0.0, 1.0), 4, points.iter().cloned()))
+}
 
-                QuadTreeRegion {
-                    x: self.x + halfwidth,
-                    y: self.y + halfheight,
-                    width: halfwidth,
-                    height: halfheight
-                }
-            ]
-        }
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_arena_build_by_insert(b: &mut Bencher) {
+    use ArenaNTree;
 
-        fn overlaps(&self, other: &QuadTreeRegion) -> bool {
-            other.contains(&Vec2 { x: self.x, y: self.y })
-                || other.contains(&Vec2 { x: self.x + self.width, y: self.y })
-                || other.contains(&Vec2 { x: self.x, y: self.y + self.height })
-                || other.contains(&Vec2 { x: self.x + self.width, y: self.y + self.height })
-        }
-    }
+    let points = random_points(10000);
+    b.iter(|| {
+        let mut ntree = ArenaNTree::new(QuadTreeRegion::square(0.0, 0.0, 1.0), 4);
+        for p in &points { ntree.insert(*p); }
+        ntree
+    })
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_arena_range_query_large(b: &mut Bencher) {
+    use ArenaNTree;
+
+    let mut rng: XorShiftRng = random();
+    let mut ntree = ArenaNTree::new(QuadTreeRegion::square(0.0, 0.0, 1.0), 4);
+    for p in random_points(10000) { ntree.insert(p); }
+
+    b.iter(|| {
+        let r = QuadTreeRegion {
+            x: rng.gen(),
+            y: rng.gen(),
+            width: rng.gen(),
+            height: rng.gen()
+        };
+
+        for p in ntree.range_query(&r) { test::black_box(p); }
+    })
+}
+
+// Remove and re-insert half the points, so buckets merge and split again.
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_churn(b: &mut Bencher) {
+    let points = random_points(10000);
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 1.0), 4);
+    for p in &points { ntree.insert(*p); }
+
+    b.iter(|| {
+        for p in &points[..5000] { ntree.remove(p); }
+        for p in &points[..5000] { ntree.insert(*p); }
+    })
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_arena_churn(b: &mut Bencher) {
+    use ArenaNTree;
+
+    let points = random_points(10000);
+    let mut ntree = ArenaNTree::new(QuadTreeRegion::square(0.0, 0.0, 1.0), 4);
+    for p in &points { ntree.insert(*p); }
+
+    b.iter(|| {
+        for p in &points[..5000] { ntree.remove(p); }
+        for p in &points[..5000] { ntree.insert(*p); }
+    })
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_nearby(b: &mut Bencher) {
+    let mut rng: XorShiftRng = random();
+    let mut ntree = NTree::new(QuadTreeRegion::square(0.0, 0.0, 1.0), 4);
+    for p in random_points(10000) { ntree.insert(p); }
+
+    b.iter(|| {
+        let p = Vec2 { x: rng.gen(), y: rng.gen() };
+        for p in ntree.nearby(&p).unwrap() { test::black_box(p); }
+    })
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn bench_arena_nearby(b: &mut Bencher) {
+    use ArenaNTree;
+
+    let mut rng: XorShiftRng = random();
+    let mut ntree = ArenaNTree::new(QuadTreeRegion::square(0.0, 0.0, 1.0), 4);
+    for p in random_points(10000) { ntree.insert(p); }
+
+    b.iter(|| {
+        let p = Vec2 { x: rng.gen(), y: rng.gen() };
+        for p in ntree.nearby(&p).unwrap() { test::black_box(p); }
+    })
+}
+
+mod regions {
+    use regions::{BoxRegion, OctreeRegion, Point, QuadTreeRegion, Vec2, Vec3};
+    use {Region};
 
     #[test]
     fn test_contains() {
@@ -210,6 +1635,21 @@ mod fixtures {
         assert!(QuadTreeRegion::square(0.0, 0.0, 100.0).overlaps(&QuadTreeRegion::square(50.0, 50.0, 100.0)));
     }
 
+    #[test]
+    fn test_overlaps_without_corners() {
+        let big = QuadTreeRegion::square(0.0, 0.0, 100.0);
+        let small = QuadTreeRegion::square(40.0, 40.0, 10.0);
+        assert!(big.overlaps(&small));
+        assert!(small.overlaps(&big));
+
+        let wide = QuadTreeRegion { x: -10.0, y: 40.0, width: 120.0, height: 10.0 };
+        let tall = QuadTreeRegion { x: 40.0, y: -10.0, width: 10.0, height: 120.0 };
+        assert!(wide.overlaps(&tall));
+        assert!(tall.overlaps(&wide));
+
+        assert!(!big.overlaps(&QuadTreeRegion::square(101.0, 50.0, 10.0)));
+    }
+
     #[test]
     fn test_split() {
         let fifty = 100.0 / 2.0;
@@ -245,4 +1685,70 @@ mod fixtures {
             ]
         )
     }
+
+    #[test]
+    fn test_contains_region() {
+        let big = QuadTreeRegion::square(0.0, 0.0, 100.0);
+        assert!(big.contains_region(&QuadTreeRegion::square(10.0, 10.0, 10.0)));
+        assert!(big.contains_region(&big));
+        assert!(!big.contains_region(&QuadTreeRegion::square(95.0, 10.0, 10.0)));
+
+        let cube = OctreeRegion::cube(0.0, 0.0, 0.0, 10.0);
+        assert!(cube.contains_region(&OctreeRegion::cube(1.0, 2.0, 3.0, 4.0)));
+        assert!(!cube.contains_region(&OctreeRegion::cube(1.0, 2.0, 7.0, 4.0)));
+
+        let b = BoxRegion::new([0.0, 0.0], [10.0, 10.0]);
+        assert!(b.contains_region(&BoxRegion::new([1.0, 1.0], [2.0, 10.0])));
+        assert!(!b.contains_region(&BoxRegion::new([-1.0, 1.0], [2.0, 10.0])));
+    }
+
+    #[test]
+    fn test_octree_split() {
+        let octants = OctreeRegion::cube(0.0, 0.0, 0.0, 2.0).split();
+        assert_eq!(octants.len(), 8);
+        assert!(octants.contains(&OctreeRegion::cube(1.0, 0.0, 1.0, 1.0)));
+
+        // Every interior point lands in exactly one octant.
+        let p = Vec3 { x: 0.5, y: 1.5, z: 0.25 };
+        assert_eq!(octants.iter().filter(|o| o.contains(&p)).count(), 1);
+    }
+
+    #[test]
+    fn test_octree_overlaps() {
+        let cube = OctreeRegion::cube(0.0, 0.0, 0.0, 10.0);
+        assert!(cube.overlaps(&OctreeRegion::cube(2.0, 2.0, 2.0, 1.0)));
+        assert!(cube.overlaps(&OctreeRegion::cube(9.0, 9.0, 9.0, 5.0)));
+        assert!(!cube.overlaps(&OctreeRegion::cube(2.0, 2.0, 11.0, 1.0)));
+    }
+
+    #[test]
+    fn test_box_region_split() {
+        let halves = BoxRegion::new([0.0], [4.0]).split();
+        assert_eq!(halves, vec![BoxRegion::new([0.0], [2.0]), BoxRegion::new([2.0], [4.0])]);
+
+        let boxes = BoxRegion::cube([0.0; 4], 1.0).split();
+        assert_eq!(boxes.len(), 16);
+
+        let p = Point([0.1, 0.6, 0.3, 0.9]);
+        let owners: Vec<&BoxRegion<4>> = boxes.iter().filter(|b| b.contains(&p)).collect();
+        assert_eq!(owners, vec![&BoxRegion::new([0.0, 0.5, 0.0, 0.5], [0.5, 1.0, 0.5, 1.0])]);
+    }
+
+    #[test]
+    fn test_box_region_overlaps() {
+        let a = BoxRegion::new([0.0, 0.0], [10.0, 10.0]);
+        assert!(a.overlaps(&BoxRegion::new([4.0, -5.0], [6.0, 15.0])));
+        assert!(!a.overlaps(&BoxRegion::new([11.0, 0.0], [12.0, 10.0])));
+    }
+
+    #[test]
+    fn test_quadtree_region_bounds() {
+        use {Bounds, Coordinates};
+
+        let r = QuadTreeRegion { x: 1.0, y: 2.0, width: 3.0, height: 4.0 };
+        assert_eq!((r.lower(0), r.upper(0), r.lower(1), r.upper(1)), (1.0, 4.0, 2.0, 6.0));
+
+        let p = Vec2 { x: 5.0, y: 6.0 };
+        assert_eq!((p.dimensions(), p.coordinate(0), p.coordinate(1)), (2, 5.0, 6.0));
+    }
 }